@@ -16,11 +16,11 @@ fn main() -> Result<(), Error> {
                 }
                 Ok(s) => s.into_bytes(),
                 Err(e) => match e.error() {
-                    Error::Io(_) => return Err(e.into()),
                     Error::Utf8(_) => {
                         eprintln!("decode error: {e}");
                         e.into_bytes()
                     }
+                    _ => return Err(e.into()),
                 },
             },
         );
@@ -0,0 +1,488 @@
+//! A caching agent that holds recently entered passphrases, in the style of `sudo`’s credential
+//! cache.
+
+use std::{
+    collections::HashMap,
+    env,
+    ffi::CStr,
+    fs,
+    io::{self, Read, Write},
+    mem::{self, MaybeUninit},
+    os::{
+        fd::AsRawFd,
+        unix::{
+            fs::PermissionsExt,
+            net::{UnixListener, UnixStream},
+        },
+    },
+    path::{Path, PathBuf},
+    ptr,
+    time::{Duration, Instant},
+};
+
+use crate::{Backend, Error, LockedBuf, Options, Zeroizing, backend};
+
+/// The longest key or passphrase the agent will take.
+const MAX_FIELD: usize = 1 << 12;
+
+/// How long either side waits on the other before giving up.
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Requests, each of which is followed by a key unless noted.
+const GET: u8 = b'G';
+/// Followed by the key and the passphrase.
+const PUT: u8 = b'P';
+const DELETE: u8 = b'D';
+/// Not followed by a key.
+const CLEAR: u8 = b'C';
+/// Not followed by a key.
+const CLEAR_ALL: u8 = b'A';
+
+/// Responses. [`OK`] is followed by the passphrase in response to [`GET`].
+const OK: u8 = b'+';
+const MISSING: u8 = b'-';
+const FAILED: u8 = b'!';
+
+/// An agent that caches passphrases in locked memory, for clients using [`AgentCache`].
+///
+/// Passphrases are kept for [`Agent::ttl`] after being stored, and are scoped to the user and the
+/// controlling terminal of the client that stored them, so that e.g. a passphrase entered in one
+/// terminal is not returned to a program running in another. Processes without a controlling
+/// terminal share one scope per user. The client’s credentials are taken from the socket with
+/// `SO_PEERCRED`, and clients running as other users are refused unless the agent runs as root.
+/// Clients likewise check that the agent runs as themselves or as root before sending it anything.
+///
+/// The `agent-bin` feature builds `readpassphrase-agent`, which runs an agent and clears it.
+#[derive(Debug)]
+pub struct Agent {
+    listener: UnixListener,
+    path: PathBuf,
+    /// How long passphrases are kept for.
+    pub ttl: Duration,
+    entries: HashMap<Scope, Entry>,
+}
+
+/// A [`Backend`] that asks an [`Agent`] for a cached passphrase before reading one with another
+/// backend, and caches any passphrase it reads.
+///
+/// The agent is optional: if there is no socket, or the agent is not running or fails, the
+/// passphrase is read from [`backend`][Self::backend] and not cached.
+/// ```no_run
+/// # use readpassphrase_3::{AgentCache, Backend, Error, Options};
+/// # fn check(_: &str) -> bool { true }
+/// # fn main() -> Result<(), Error> {
+/// let cache = AgentCache::new("myapp:unlock", Options::default());
+/// let pass = cache.getpass(c"Unlock passphrase: ")?;
+/// if !check(&pass) {
+///     cache.invalidate()?;
+/// }
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct AgentCache<B = Options> {
+    /// The agent’s socket, [`Agent::default_socket`] by default. If this is [`None`], the agent is
+    /// not used.
+    pub socket: Option<PathBuf>,
+    /// The key to cache the passphrase under.
+    pub key: String,
+    /// The backend to read the passphrase with when it is not cached.
+    pub backend: B,
+}
+
+#[derive(Debug, PartialEq, Eq, Hash)]
+struct Scope {
+    uid: libc::uid_t,
+    tty: u64,
+    key: String,
+}
+
+#[derive(Debug)]
+struct Entry {
+    pass: LockedBuf,
+    expires: Instant,
+}
+
+impl Agent {
+    /// Returns the default socket path, `$XDG_RUNTIME_DIR/readpassphrase-agent.sock`, or [`None`]
+    /// if `XDG_RUNTIME_DIR` is not set.
+    ///
+    /// There is no fallback to a shared directory such as `/tmp`, where another user could bind
+    /// the path first.
+    pub fn default_socket() -> Option<PathBuf> {
+        env::var_os("XDG_RUNTIME_DIR")
+            .filter(|dir| !dir.is_empty())
+            .map(|dir| PathBuf::from(dir).join("readpassphrase-agent.sock"))
+    }
+
+    /// Listens on `path`, keeping passphrases for `ttl`.
+    ///
+    /// A stale socket left at `path` by an agent that has exited is replaced. The socket is only
+    /// accessible to the current user, unless that is root, in which case any user may connect
+    /// and is served their own scope.
+    ///
+    /// # Errors
+    /// Returns [`Err`] if `path` could not be bound, e.g. because an agent is already running.
+    pub fn bind(path: impl Into<PathBuf>, ttl: Duration) -> io::Result<Agent> {
+        let path = path.into();
+        if UnixStream::connect(&path).is_err() {
+            _ = fs::remove_file(&path);
+        }
+        let listener = UnixListener::bind(&path)?;
+        // Until this takes effect, the socket has the permissions given by the umask; clients of
+        // other users are still refused by `serve`.
+        // SAFETY: this has no preconditions.
+        let mode = if unsafe { libc::getuid() } == 0 {
+            0o666
+        } else {
+            0o600
+        };
+        fs::set_permissions(&path, fs::Permissions::from_mode(mode))?;
+        Ok(Agent {
+            listener,
+            path,
+            ttl,
+            entries: HashMap::new(),
+        })
+    }
+
+    /// Serves clients until an error occurs accepting a connection. Errors serving a client only
+    /// end that connection.
+    ///
+    /// # Errors
+    /// Returns [`Err`] if waiting for or accepting a connection fails.
+    pub fn run(&mut self) -> io::Result<()> {
+        loop {
+            let now = Instant::now();
+            self.entries.retain(|_, entry| entry.expires > now);
+            // Wake up in time to drop the next passphrase to expire.
+            let timeout = match self.entries.values().map(|entry| entry.expires).min() {
+                Some(expires) => {
+                    let left = expires.saturating_duration_since(now).as_micros();
+                    left.div_ceil(1000).try_into().unwrap_or(libc::c_int::MAX)
+                }
+                None => -1,
+            };
+            let mut fd = libc::pollfd {
+                fd: self.listener.as_raw_fd(),
+                events: libc::POLLIN,
+                revents: 0,
+            };
+            // SAFETY: `fd` is a valid `pollfd`.
+            match unsafe { libc::poll(&mut fd, 1, timeout) } {
+                -1 => {
+                    let err = io::Error::last_os_error();
+                    if err.kind() != io::ErrorKind::Interrupted {
+                        return Err(err);
+                    }
+                }
+                0 => {}
+                _ => match self.listener.accept() {
+                    Ok((stream, _)) => _ = self.serve(stream),
+                    Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                    Err(e) => return Err(e),
+                },
+            }
+        }
+    }
+
+    /// Clears passphrases cached by the agent at `socket` for the current user, in the scope of
+    /// the current terminal or, if `all` is set, in every scope.
+    ///
+    /// # Errors
+    /// Returns [`Err`] if the agent could not be reached or failed.
+    pub fn clear(socket: &Path, all: bool) -> io::Result<()> {
+        let op = if all { CLEAR_ALL } else { CLEAR };
+        request(socket, &[op])?;
+        Ok(())
+    }
+
+    fn serve(&mut self, mut stream: UnixStream) -> io::Result<()> {
+        stream.set_read_timeout(Some(TIMEOUT))?;
+        stream.set_write_timeout(Some(TIMEOUT))?;
+        let cred = peer_cred(&stream)?;
+        // SAFETY: this has no preconditions.
+        let our_uid = unsafe { libc::getuid() };
+        if cred.uid != our_uid && our_uid != 0 {
+            return Err(io::ErrorKind::PermissionDenied.into());
+        }
+        let tty = tty_of(cred.pid)?;
+        let mut op = 0;
+        stream.read_exact(slice_of(&mut op))?;
+        let scope = |key| Scope {
+            uid: cred.uid,
+            tty,
+            key,
+        };
+        match op {
+            GET => {
+                let key = read_key(&mut stream)?;
+                match self.entries.get(&scope(key)) {
+                    Some(entry) => {
+                        stream.write_all(&[OK])?;
+                        write_len(&mut stream, entry.pass.len())?;
+                        stream.write_all(&entry.pass)
+                    }
+                    None => stream.write_all(&[MISSING]),
+                }
+            }
+            PUT => {
+                let key = read_key(&mut stream)?;
+                let len = read_len(&mut stream)?;
+                let mut pass = LockedBuf::new(len)?;
+                stream.read_exact(&mut pass)?;
+                let expires = Instant::now() + self.ttl;
+                self.entries.insert(scope(key), Entry { pass, expires });
+                stream.write_all(&[OK])
+            }
+            DELETE => {
+                let key = read_key(&mut stream)?;
+                self.entries.remove(&scope(key));
+                stream.write_all(&[OK])
+            }
+            CLEAR => {
+                self.entries
+                    .retain(|scope, _| scope.uid != cred.uid || scope.tty != tty);
+                stream.write_all(&[OK])
+            }
+            CLEAR_ALL => {
+                self.entries.retain(|scope, _| scope.uid != cred.uid);
+                stream.write_all(&[OK])
+            }
+            _ => stream.write_all(&[FAILED]),
+        }
+    }
+}
+
+impl Drop for Agent {
+    fn drop(&mut self) {
+        _ = fs::remove_file(&self.path);
+    }
+}
+
+impl<B: Backend> AgentCache<B> {
+    /// Returns a cache of passphrases read with `backend`, stored under `key` by the agent at
+    /// [`Agent::default_socket`].
+    pub fn new(key: impl Into<String>, backend: B) -> Self {
+        AgentCache {
+            socket: Agent::default_socket(),
+            key: key.into(),
+            backend,
+        }
+    }
+
+    /// Removes the cached passphrase, if there is one, so that the next read prompts again.
+    ///
+    /// # Errors
+    /// Returns [`Error::Io`] if the agent could not be reached or failed.
+    pub fn invalidate(&self) -> Result<(), Error> {
+        if let Some(socket) = &self.socket {
+            request(socket, &self.message(DELETE, b"")?)?;
+        }
+        Ok(())
+    }
+
+    /// Reads the cached passphrase into `buf`, returning its full length if there is one. It is
+    /// only read if it fits.
+    fn get(&self, buf: &mut [u8]) -> io::Result<Option<usize>> {
+        let Some(socket) = &self.socket else {
+            return Ok(None);
+        };
+        let mut stream = request(socket, &self.message(GET, b"")?)?;
+        let len = read_len(&mut stream)?;
+        if len < buf.len() {
+            stream.read_exact(&mut buf[..len])?;
+        }
+        Ok(Some(len))
+    }
+
+    /// Stores `pass` with the agent.
+    fn put(&self, pass: &[u8]) -> io::Result<()> {
+        if let Some(socket) = &self.socket {
+            request(socket, &self.message(PUT, pass)?)?;
+        }
+        Ok(())
+    }
+
+    /// Returns a request of `op` for this cache’s key, followed by `pass` if `op` is [`PUT`].
+    fn message(&self, op: u8, pass: &[u8]) -> io::Result<Zeroizing<Vec<u8>>> {
+        if self.key.len() > MAX_FIELD || pass.len() > MAX_FIELD {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "key or passphrase is too long for the agent",
+            ));
+        }
+        let mut message = Zeroizing::new(Vec::with_capacity(5 + self.key.len() + pass.len()));
+        message.push(op);
+        message.extend_from_slice(&(self.key.len() as u16).to_be_bytes());
+        message.extend_from_slice(self.key.as_bytes());
+        if op == PUT {
+            message.extend_from_slice(&(pass.len() as u16).to_be_bytes());
+            message.extend_from_slice(pass);
+        }
+        Ok(message)
+    }
+}
+
+impl<B: Backend> Backend for AgentCache<B> {
+    fn read_bytes<'a>(
+        &self,
+        prompt: &CStr,
+        buf: &'a mut [MaybeUninit<u8>],
+    ) -> Result<&'a mut [u8], Error> {
+        backend::read_cached(
+            &self.backend,
+            prompt,
+            buf,
+            |buf| self.get(buf),
+            |pass| self.put(pass),
+        )
+    }
+}
+
+/// Sends `message` to the agent at `socket`, returning the stream if it succeeded, so that any
+/// response can be read from it.
+///
+/// The agent must be running as the current user or as root; anyone else listening on `socket`
+/// is refused before `message` is sent.
+fn request(socket: &Path, message: &[u8]) -> io::Result<UnixStream> {
+    let mut stream = UnixStream::connect(socket)?;
+    let cred = peer_cred(&stream)?;
+    // SAFETY: this has no preconditions.
+    if cred.uid != unsafe { libc::getuid() } && cred.uid != 0 {
+        return Err(io::Error::new(
+            io::ErrorKind::PermissionDenied,
+            "passphrase agent is running as another user",
+        ));
+    }
+    stream.set_read_timeout(Some(TIMEOUT))?;
+    stream.set_write_timeout(Some(TIMEOUT))?;
+    stream.write_all(message)?;
+    let mut status = 0;
+    stream.read_exact(slice_of(&mut status))?;
+    match status {
+        OK => Ok(stream),
+        MISSING => Err(io::ErrorKind::NotFound.into()),
+        _ => Err(io::Error::other("passphrase agent failed")),
+    }
+}
+
+fn read_key(stream: &mut UnixStream) -> io::Result<String> {
+    let mut key = vec![0; read_len(stream)?];
+    stream.read_exact(&mut key)?;
+    String::from_utf8(key).map_err(|_| io::ErrorKind::InvalidData.into())
+}
+
+fn read_len(stream: &mut UnixStream) -> io::Result<usize> {
+    let mut len = [0; 2];
+    stream.read_exact(&mut len)?;
+    match u16::from_be_bytes(len).into() {
+        len if len <= MAX_FIELD => Ok(len),
+        _ => Err(io::ErrorKind::InvalidData.into()),
+    }
+}
+
+fn write_len(stream: &mut UnixStream, len: usize) -> io::Result<()> {
+    stream.write_all(&(len as u16).to_be_bytes())
+}
+
+fn slice_of(b: &mut u8) -> &mut [u8] {
+    std::slice::from_mut(b)
+}
+
+/// Returns the credentials of the process at the other end of `stream`.
+fn peer_cred(stream: &UnixStream) -> io::Result<libc::ucred> {
+    // SAFETY: `ucred` is plain old data, for which zero is a valid value.
+    let mut cred: libc::ucred = unsafe { mem::zeroed() };
+    let mut len = mem::size_of_val(&cred) as libc::socklen_t;
+    // SAFETY: `cred` is writable for `len` bytes.
+    let res = unsafe {
+        libc::getsockopt(
+            stream.as_raw_fd(),
+            libc::SOL_SOCKET,
+            libc::SO_PEERCRED,
+            ptr::from_mut(&mut cred).cast(),
+            &mut len,
+        )
+    };
+    if res == -1 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(cred)
+}
+
+/// Returns the device number of the controlling terminal of `pid`, or 0 if it has none.
+fn tty_of(pid: libc::pid_t) -> io::Result<u64> {
+    let stat = fs::read_to_string(format!("/proc/{pid}/stat"))?;
+    // The fields after the command name, which may contain spaces, start with the state; the
+    // terminal is the fifth of them.
+    let (_, fields) = stat.rsplit_once(')').ok_or(io::ErrorKind::InvalidData)?;
+    fields
+        .split_whitespace()
+        .nth(4)
+        .and_then(|tty| tty.parse().ok())
+        .ok_or(io::ErrorKind::InvalidData.into())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{cell::Cell, process, thread};
+
+    use super::*;
+
+    /// A backend that counts its reads, returning `pass1`, `pass2` and so on.
+    #[derive(Default)]
+    struct Counter(Cell<u32>);
+
+    impl Backend for Counter {
+        fn read_bytes<'a>(
+            &self,
+            _: &CStr,
+            buf: &'a mut [MaybeUninit<u8>],
+        ) -> Result<&'a mut [u8], Error> {
+            self.0.set(self.0.get() + 1);
+            let pass = format!("pass{}", self.0.get());
+            backend::read_zeroed(buf, |buf| {
+                backend::read_line(pass.as_bytes(), buf).map_err(Into::into)
+            })
+        }
+    }
+
+    #[test]
+    fn test_agent() {
+        let socket = env::temp_dir().join(format!("readpassphrase-agent-{}", process::id()));
+        let mut agent = Agent::bind(&socket, Duration::from_millis(500)).unwrap();
+        thread::spawn(move || agent.run());
+
+        let cache = AgentCache {
+            socket: Some(socket.clone()),
+            key: "test".into(),
+            backend: Counter::default(),
+        };
+        assert_eq!("pass1", cache.getpass(c"").unwrap());
+        assert_eq!("pass1", cache.getpass(c"").unwrap());
+        // The cached passphrase does not fit, so the truncated new one replaces it.
+        let mut buf = [0u8; 4];
+        assert_eq!("pas", cache.readpassphrase(c"", &mut buf).unwrap());
+        assert_eq!("pas", cache.getpass(c"").unwrap());
+        cache.invalidate().unwrap();
+        assert_eq!("pass3", cache.getpass(c"").unwrap());
+        Agent::clear(&socket, false).unwrap();
+        assert_eq!("pass4", cache.getpass(c"").unwrap());
+        Agent::clear(&socket, true).unwrap();
+        assert_eq!("pass5", cache.getpass(c"").unwrap());
+        thread::sleep(Duration::from_millis(600));
+        assert_eq!("pass6", cache.getpass(c"").unwrap());
+
+        // Without an agent, every read prompts.
+        let mut cache = AgentCache {
+            socket: Some(socket.with_extension("missing")),
+            ..cache
+        };
+        assert_eq!("pass7", cache.getpass(c"").unwrap());
+        assert_eq!("pass8", cache.getpass(c"").unwrap());
+        cache.socket = None;
+        assert_eq!("pass9", cache.getpass(c"").unwrap());
+        cache.invalidate().unwrap();
+    }
+}
@@ -0,0 +1,93 @@
+//! Fixed-capacity passphrases.
+
+use std::ffi::CStr;
+
+use crate::{Backend, Error, Flags, Options, Passphrase};
+
+/// A passphrase of up to `N - 1` bytes stored inline, returned by [`readpassphrase_array`].
+///
+/// This dereferences to [`str`](prim@str), and zeroes its storage when dropped.
+///
+/// # Security
+/// Like any Rust value, a `PassphraseArray` may be copied in memory when it is moved, leaving the
+/// old copy behind un-zeroed. To avoid this, create it where it will stay with
+/// [`PassphraseArray::new`], read into it with [`readpassphrase_array_in_place`], and only ever
+/// pass it by reference.
+pub type PassphraseArray<const N: usize> = Passphrase<[u8; N]>;
+
+/// Reads a passphrase using `readpassphrase(3)` into a [`PassphraseArray`] of `N` bytes, without
+/// allocating.
+///
+/// As with [`readpassphrase`][crate::readpassphrase], the passphrase may be up to `N - 1` bytes
+/// long; any additional characters and the terminating newline are discarded.
+/// ```no_run
+/// # use readpassphrase_3::{Error, Flags, readpassphrase_array};
+/// # fn main() -> Result<(), Error> {
+/// let pass = readpassphrase_array::<128>(c"PIN: ", Flags::REQUIRE_TTY)?;
+/// let pin: &str = &pass;
+/// # Ok(())
+/// # }
+/// ```
+///
+/// The passphrase is moved when it is returned; see [`readpassphrase_array_in_place`] to avoid
+/// this.
+///
+/// # Errors
+/// As with [`readpassphrase`][crate::readpassphrase]. No partial passphrase is kept in this case.
+pub fn readpassphrase_array<const N: usize>(
+    prompt: &CStr,
+    flags: Flags,
+) -> Result<PassphraseArray<N>, Error> {
+    Options::from(flags).readpassphrase_array(prompt)
+}
+
+/// Reads a passphrase using `readpassphrase(3)` into `pass`, replacing what it held.
+///
+/// This is like [`readpassphrase_array`], but reads into storage owned by the caller, so that the
+/// passphrase is never moved once read:
+/// ```no_run
+/// # use readpassphrase_3::{Error, Flags, PassphraseArray, readpassphrase_array_in_place};
+/// # fn main() -> Result<(), Error> {
+/// let mut pass = PassphraseArray::<128>::new();
+/// readpassphrase_array_in_place(c"PIN: ", &mut pass, Flags::REQUIRE_TTY)?;
+/// let pin: &str = &pass;
+/// # Ok(())
+/// # }
+/// ```
+///
+/// # Errors
+/// As with [`readpassphrase_array`]. `pass` is left empty and zeroed in this case.
+pub fn readpassphrase_array_in_place<const N: usize>(
+    prompt: &CStr,
+    pass: &mut PassphraseArray<N>,
+    flags: Flags,
+) -> Result<(), Error> {
+    Options::from(flags).readpassphrase_array_in_place(prompt, pass)
+}
+
+impl Options {
+    /// Reads a passphrase as with [`readpassphrase_array`], using these options.
+    ///
+    /// # Errors
+    /// As with [`Options::readpassphrase`].
+    pub fn readpassphrase_array<const N: usize>(
+        &self,
+        prompt: &CStr,
+    ) -> Result<PassphraseArray<N>, Error> {
+        let mut pass = PassphraseArray::new();
+        self.readpassphrase_array_in_place(prompt, &mut pass)?;
+        Ok(pass)
+    }
+
+    /// Reads a passphrase as with [`readpassphrase_array_in_place`], using these options.
+    ///
+    /// # Errors
+    /// As with [`Options::readpassphrase`]. `pass` is left empty and zeroed in this case.
+    pub fn readpassphrase_array_in_place<const N: usize>(
+        &self,
+        prompt: &CStr,
+        pass: &mut PassphraseArray<N>,
+    ) -> Result<(), Error> {
+        pass.read_in_place(|buf| self.read_bytes(prompt, buf))
+    }
+}
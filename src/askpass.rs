@@ -0,0 +1,154 @@
+//! Reading passphrases from `SSH_ASKPASS`-style programs.
+
+use std::{
+    env,
+    ffi::{CStr, OsString},
+    io,
+    mem::MaybeUninit,
+    path::PathBuf,
+    process::{Command, Stdio},
+};
+
+use crate::{Backend, Error, Options, backend, term};
+
+/// A [`Backend`] that runs an askpass program, such as those named by `SSH_ASKPASS` or
+/// `SUDO_ASKPASS`.
+///
+/// The program is passed the prompt as its only argument, and is expected to write the
+/// passphrase followed by a newline to its stdout and exit successfully. Exiting with status 1,
+/// as `ssh-askpass` does when the user cancels, is reported as [`Error::Cancelled`].
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct AskPass {
+    program: PathBuf,
+}
+
+/// A [`Backend`] that reads from the terminal if there is one, and otherwise runs an askpass
+/// program.
+///
+/// This is for programs that may be run without a controlling terminal, e.g. from a desktop
+/// launcher or an IDE:
+/// ```no_run
+/// # use readpassphrase_3::{Backend, Error, Flags, TtyOrAskPass};
+/// # fn main() -> Result<(), Error> {
+/// let backend = TtyOrAskPass::from_env(Flags::REQUIRE_TTY.into());
+/// let pass = backend.getpass(c"Password: ")?;
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct TtyOrAskPass {
+    /// Options for reading from the terminal.
+    pub options: Options,
+    /// The program to run if there is no terminal. If this is [`None`], the terminal is always
+    /// used.
+    pub askpass: Option<AskPass>,
+}
+
+impl AskPass {
+    /// Returns a backend that runs `program`.
+    pub fn new(program: impl Into<PathBuf>) -> Self {
+        AskPass {
+            program: program.into(),
+        }
+    }
+
+    /// Returns a backend that runs the program named by `SSH_ASKPASS`, or failing that
+    /// `SUDO_ASKPASS`, if either is set to a non-empty value.
+    pub fn from_env() -> Option<Self> {
+        ["SSH_ASKPASS", "SUDO_ASKPASS"]
+            .into_iter()
+            .filter_map(env::var_os)
+            .find(|program| !program.is_empty())
+            .map(AskPass::new)
+    }
+
+    fn run(&self, prompt: &CStr, buf: &mut [u8]) -> Result<usize, Error> {
+        let mut child = Command::new(&self.program)
+            .arg(prompt_arg(prompt))
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .spawn()?;
+        let mut stdout = child.stdout.take().unwrap();
+        let res = backend::read_line(&mut stdout, buf).and_then(|len| {
+            backend::drain(stdout)?;
+            Ok(len)
+        });
+        let status = child.wait();
+        let len = res?;
+        match status?.code() {
+            Some(0) => Ok(len),
+            Some(1) => Err(Error::Cancelled),
+            _ => Err(io::Error::other(format!("{} failed", self.program.display())).into()),
+        }
+    }
+}
+
+impl Backend for AskPass {
+    fn read_bytes<'a>(
+        &self,
+        prompt: &CStr,
+        buf: &'a mut [MaybeUninit<u8>],
+    ) -> Result<&'a mut [u8], Error> {
+        backend::read_zeroed(buf, |buf| self.run(prompt, buf))
+    }
+}
+
+impl TtyOrAskPass {
+    /// Returns a backend that reads from the terminal with `options`, or otherwise runs the
+    /// askpass program given by the environment as in [`AskPass::from_env`].
+    pub fn from_env(options: Options) -> Self {
+        TtyOrAskPass {
+            options,
+            askpass: AskPass::from_env(),
+        }
+    }
+}
+
+impl Backend for TtyOrAskPass {
+    fn read_bytes<'a>(
+        &self,
+        prompt: &CStr,
+        buf: &'a mut [MaybeUninit<u8>],
+    ) -> Result<&'a mut [u8], Error> {
+        match &self.askpass {
+            Some(askpass) if !cfg!(windows) && !term::tty_available() => {
+                askpass.read_bytes(prompt, buf)
+            }
+            _ => self.options.read_bytes(prompt, buf),
+        }
+    }
+}
+
+#[cfg(unix)]
+fn prompt_arg(prompt: &CStr) -> OsString {
+    use std::os::unix::ffi::OsStrExt;
+
+    std::ffi::OsStr::from_bytes(prompt.to_bytes()).to_owned()
+}
+
+#[cfg(not(unix))]
+fn prompt_arg(prompt: &CStr) -> OsString {
+    prompt.to_string_lossy().into_owned().into()
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+
+    const FAKE: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fake-askpass.sh");
+
+    #[test]
+    fn test_askpass() {
+        let pass = AskPass::new("echo").getpass(c"pass\rword").unwrap();
+        assert_eq!("pass\rword", pass);
+        let mut buf = [0u8; 5];
+        let pass = AskPass::new("echo")
+            .readpassphrase(c"password", &mut buf)
+            .unwrap();
+        assert_eq!("pass", pass);
+        let err = AskPass::new(FAKE).getpass(c"exit 1").unwrap_err();
+        assert!(matches!(err, Error::Cancelled));
+        let err = AskPass::new(FAKE).getpass(c"exit 2").unwrap_err();
+        assert!(matches!(err, Error::Io(_)));
+    }
+}
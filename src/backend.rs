@@ -0,0 +1,259 @@
+//! Pluggable ways of reading a passphrase.
+
+use std::{
+    ffi::CStr,
+    io::{self, Read},
+    mem::MaybeUninit,
+    ptr, str,
+};
+
+use crate::{Error, IntoError, Options, PASSWORD_LEN, SecretBuf, Zeroize};
+
+/// A way of reading a passphrase, e.g. from the terminal or from another program.
+///
+/// [`Options`] is the backend that reads from the terminal using `readpassphrase(3)`. Other
+/// backends provide the same API for reading from elsewhere:
+/// ```no_run
+/// # use readpassphrase_3::{AskPass, Backend, Error};
+/// # fn main() -> Result<(), Error> {
+/// let askpass = AskPass::new("/usr/lib/ssh/ssh-askpass");
+/// let pass = askpass.getpass(c"Password: ")?;
+/// # Ok(())
+/// # }
+/// ```
+pub trait Backend {
+    /// Reads a passphrase with `prompt` into `buf`, returning the part of `buf` that holds it.
+    ///
+    /// Implementations must only write initialized bytes to `buf`, and must return a prefix of
+    /// it. As with [`readpassphrase`][crate::readpassphrase], the passphrase should be at most
+    /// `buf.len() - 1` bytes long.
+    ///
+    /// # Errors
+    /// Returns [`Err`] if the passphrase could not be read. Any part of it that was read should
+    /// be zeroed in this case.
+    fn read_bytes<'a>(
+        &self,
+        prompt: &CStr,
+        buf: &'a mut [MaybeUninit<u8>],
+    ) -> Result<&'a mut [u8], Error>;
+
+    /// Reads a passphrase as with [`readpassphrase`][crate::readpassphrase], using this backend.
+    ///
+    /// # Errors
+    /// As with [`Backend::read_bytes`], or if the passphrase is not UTF-8.
+    fn readpassphrase<'a>(&self, prompt: &CStr, buf: &'a mut [u8]) -> Result<&'a str, Error> {
+        // SAFETY: `read_bytes` only writes initialized bytes to `buf`.
+        let buf = unsafe { &mut *(ptr::from_mut(buf) as *mut [MaybeUninit<u8>]) };
+        Ok(str::from_utf8(self.read_bytes(prompt, buf)?)?)
+    }
+
+    /// Reads a passphrase as with [`readpassphrase_into`][crate::readpassphrase_into], using this
+    /// backend.
+    ///
+    /// # Errors
+    /// As with [`Backend::readpassphrase`]. The buffer is returned in the error.
+    fn readpassphrase_into<B: SecretBuf>(
+        &self,
+        prompt: &CStr,
+        buf: B,
+    ) -> Result<B::Passphrase, IntoError<B>>
+    where
+        Self: Sized,
+    {
+        let (pass, ()) = read_into(buf, |buf| Ok((self.read_bytes(prompt, buf)?, ())))?;
+        Ok(pass)
+    }
+
+    /// Reads a passphrase as with [`getpass`][crate::getpass], using this backend.
+    ///
+    /// # Errors
+    /// As with [`Backend::readpassphrase`].
+    fn getpass(&self, prompt: &CStr) -> Result<String, Error>
+    where
+        Self: Sized,
+    {
+        let buf = Vec::with_capacity(PASSWORD_LEN);
+        Ok(self.readpassphrase_into(prompt, buf)?)
+    }
+}
+
+impl Backend for Options {
+    fn read_bytes<'a>(
+        &self,
+        prompt: &CStr,
+        buf: &'a mut [MaybeUninit<u8>],
+    ) -> Result<&'a mut [u8], Error> {
+        Ok(self.read_raw(prompt, buf)?.0)
+    }
+}
+
+/// Reads a passphrase into `buf` with `read`, which returns the prefix of the memory holding it
+/// along with anything else read, then turns `buf` into a passphrase. `buf` is returned in the
+/// error if either step fails.
+pub(crate) fn read_into<B: SecretBuf, T>(
+    mut buf: B,
+    read: impl FnOnce(&mut [MaybeUninit<u8>]) -> Result<(&mut [u8], T), Error>,
+) -> Result<(B::Passphrase, T), IntoError<B>> {
+    // SAFETY: `read` only writes initialized bytes to `buf`, as `Backend::read_bytes` does.
+    let mem = unsafe { buf.as_uninit_mut() };
+    let start = mem.as_ptr();
+    let (len, extra) = match read(mem) {
+        Ok((pass, extra)) => match prefix_len(start, pass) {
+            Ok(len) => (len, extra),
+            Err(e) => {
+                buf.wipe();
+                return Err(IntoError(e, Some(buf)));
+            }
+        },
+        Err(e) => return Err(IntoError(e, Some(buf))),
+    };
+    // SAFETY: `read` initialized the first `len` bytes of `buf`, per `prefix_len`.
+    match unsafe { buf.into_passphrase(len) } {
+        Ok(pass) => Ok((pass, extra)),
+        Err((buf, e)) => Err(IntoError(Error::Utf8(e), Some(buf))),
+    }
+}
+
+/// Returns the length of `pass`, as returned by a backend reading into memory at `start`, or an
+/// error if it is not a prefix of that memory.
+pub(crate) fn prefix_len(start: *const MaybeUninit<u8>, pass: &[u8]) -> Result<usize, Error> {
+    if pass.as_ptr().cast() == start {
+        Ok(pass.len())
+    } else {
+        Err(io::Error::new(io::ErrorKind::InvalidData, "backend returned a non-prefix").into())
+    }
+}
+
+/// Zero-fills `buf` and passes it to `read`, returning the first `len` bytes it reports reading.
+/// `buf` is zeroed again if `read` fails.
+pub(crate) fn read_zeroed(
+    buf: &mut [MaybeUninit<u8>],
+    read: impl FnOnce(&mut [u8]) -> Result<usize, Error>,
+) -> Result<&mut [u8], Error> {
+    buf.fill(MaybeUninit::new(0));
+    // SAFETY: `buf` was just initialized.
+    let buf = unsafe { &mut *(ptr::from_mut(buf) as *mut [u8]) };
+    match read(buf) {
+        Ok(len) => Ok(&mut buf[..len]),
+        Err(e) => {
+            buf.zeroize();
+            Err(e)
+        }
+    }
+}
+
+/// Reads a passphrase through a cache, for backends that cache another backend’s passphrases.
+///
+/// `get` reads the cached passphrase into `buf`, returning its length if there is one, and `put`
+/// stores a passphrase read from `backend` when there is not. The cache is only an optimization:
+/// if it fails, or holds a passphrase too long for `buf`, the passphrase is read from `backend`,
+/// and it is returned even if it could not be stored.
+#[cfg(target_os = "linux")]
+pub(crate) fn read_cached<'a>(
+    backend: &impl Backend,
+    prompt: &CStr,
+    buf: &'a mut [MaybeUninit<u8>],
+    get: impl FnOnce(&mut [u8]) -> io::Result<Option<usize>>,
+    put: impl FnOnce(&[u8]) -> io::Result<()>,
+) -> Result<&'a mut [u8], Error> {
+    let cached = read_zeroed(buf, |buf| match get(buf)? {
+        Some(len) if len < buf.len() => Ok(len),
+        // A passphrase that does not fit is prompted for instead, rather than truncated.
+        _ => Err(io::Error::from(io::ErrorKind::NotFound).into()),
+    });
+    if let Ok(pass) = cached {
+        let len = pass.len();
+        // SAFETY: `read_zeroed` initialized all of `buf`.
+        return Ok(unsafe { &mut *(ptr::from_mut(&mut buf[..len]) as *mut [u8]) });
+    }
+    let pass = backend.read_bytes(prompt, buf)?;
+    _ = put(pass);
+    Ok(pass)
+}
+
+/// Reads the first line from `reader` into `buf`, returning its length.
+///
+/// As with `readpassphrase(3)`, at most `buf.len() - 1` bytes are kept and the rest of the line is
+/// discarded. The newline, along with any carriage return before it, is not included, and the
+/// rest of `buf` is zeroed. Reading stops at the first newline or at the end of the input.
+pub(crate) fn read_line(mut reader: impl Read, buf: &mut [u8]) -> io::Result<usize> {
+    let cap = buf
+        .len()
+        .checked_sub(1)
+        .ok_or(io::Error::from(io::ErrorKind::InvalidInput))?;
+    let mut len = 0;
+    let mut rest = [0u8; 64];
+    let res = loop {
+        let dst = if len < cap {
+            &mut buf[len..cap]
+        } else {
+            // Discard anything past the end of `buf`, as `readpassphrase(3)` does.
+            &mut rest[..]
+        };
+        match reader.read(dst) {
+            Ok(0) => break Ok(()),
+            Ok(n) => {
+                let newline = dst[..n].contains(&b'\n');
+                len += if len < cap { n } else { 0 };
+                if newline {
+                    break Ok(());
+                }
+            }
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => break Err(e),
+        }
+    };
+    rest.zeroize();
+    let len = buf[..len].iter().position(|&b| b == b'\n').unwrap_or(len);
+    let len = if len > 0 && buf[len - 1] == b'\r' {
+        len - 1
+    } else {
+        len
+    };
+    buf[len..].zeroize();
+    res.map(|()| len)
+}
+
+/// Reads and discards the rest of `reader`'s input, so that a writer is not cut off.
+pub(crate) fn drain(mut reader: impl Read) -> io::Result<()> {
+    let mut rest = [0u8; 64];
+    let res = loop {
+        match reader.read(&mut rest) {
+            Ok(0) => break Ok(()),
+            Ok(_) => {}
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => break Err(e),
+        }
+    };
+    rest.zeroize();
+    res
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A backend that wrongly returns a part of `buf` other than a prefix.
+    struct Offset;
+
+    impl Backend for Offset {
+        fn read_bytes<'a>(
+            &self,
+            _: &CStr,
+            buf: &'a mut [MaybeUninit<u8>],
+        ) -> Result<&'a mut [u8], Error> {
+            let buf = read_zeroed(buf, |buf| {
+                buf[..5].copy_from_slice(b"xpass");
+                Ok(5)
+            })?;
+            Ok(&mut buf[1..])
+        }
+    }
+
+    #[test]
+    fn test_non_prefix() {
+        let err = Offset.readpassphrase_into(c"", [1u8; 8]).unwrap_err();
+        assert!(matches!(err.error(), Error::Io(e) if e.kind() == io::ErrorKind::InvalidData));
+        assert_eq!([0u8; 8], err.into_inner());
+    }
+}
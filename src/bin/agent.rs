@@ -0,0 +1,85 @@
+//! A passphrase caching agent, for programs reading passphrases with `AgentCache`.
+//!
+//! ```text
+//! readpassphrase-agent [--socket PATH] serve [--ttl SECS]
+//! readpassphrase-agent [--socket PATH] clear [--all]
+//! ```
+//!
+//! The socket is `$XDG_RUNTIME_DIR/readpassphrase-agent.sock` unless `--socket` is given.
+//!
+//! `serve` runs the agent in the foreground, keeping passphrases for `--ttl` seconds (300 by
+//! default). `clear` forgets the passphrases entered in the current terminal, or with `--all`,
+//! all of the current user’s. The exit status is 0 on success, 1 on error, and 2 on a usage error.
+
+use std::process::ExitCode;
+
+const FAILED: u8 = 1;
+const USAGE: u8 = 2;
+
+#[cfg(target_os = "linux")]
+fn main() -> ExitCode {
+    use std::{env, path::PathBuf, time::Duration};
+
+    use readpassphrase_3::{Agent, Hardening};
+
+    let mut args = env::args_os().skip(1).peekable();
+    let mut socket = Agent::default_socket();
+    if args.peek().is_some_and(|arg| arg == "--socket") {
+        args.next();
+        let Some(path) = args.next() else {
+            return usage();
+        };
+        socket = Some(PathBuf::from(path));
+    }
+    let Some(socket) = socket else {
+        eprintln!("XDG_RUNTIME_DIR is not set; pass --socket");
+        return ExitCode::from(FAILED);
+    };
+    let Some(command) = args.next() else {
+        return usage();
+    };
+    let rest: Vec<_> = args.map(|arg| arg.to_string_lossy().into_owned()).collect();
+    let res = match (command.to_str(), &rest[..]) {
+        (Some("serve"), [] | [_, _]) => {
+            let ttl = match &rest[..] {
+                [] => 300,
+                [flag, secs] if flag == "--ttl" => match secs.parse() {
+                    Ok(secs) => secs,
+                    Err(_) => return usage(),
+                },
+                _ => return usage(),
+            };
+            let _hardening = match Hardening::new() {
+                Ok(hardening) => hardening,
+                Err(e) => return failed("failed hardening the process", e),
+            };
+            Agent::bind(&socket, Duration::from_secs(ttl)).and_then(|mut agent| agent.run())
+        }
+        (Some("clear"), []) => Agent::clear(&socket, false),
+        (Some("clear"), [flag]) if flag == "--all" => Agent::clear(&socket, true),
+        _ => return usage(),
+    };
+    match res {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => failed("passphrase agent failed", e),
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn main() -> ExitCode {
+    eprintln!("readpassphrase-agent is only supported on Linux");
+    ExitCode::from(FAILED)
+}
+
+#[cfg(target_os = "linux")]
+fn failed(what: &str, e: std::io::Error) -> ExitCode {
+    eprintln!("{what}: {e}");
+    ExitCode::from(FAILED)
+}
+
+#[cfg(target_os = "linux")]
+fn usage() -> ExitCode {
+    eprintln!("usage: readpassphrase-agent [--socket PATH] serve [--ttl SECS]");
+    eprintln!("       readpassphrase-agent [--socket PATH] clear [--all]");
+    ExitCode::from(USAGE)
+}
@@ -0,0 +1,95 @@
+//! An askpass program that reads a passphrase from the terminal, for use as `SSH_ASKPASS`,
+//! `GIT_ASKPASS`, or `SUDO_ASKPASS`.
+//!
+//! The prompt is taken from the arguments, and the passphrase is written to stdout followed by a
+//! newline. The exit status is 0 on success, 1 if entry was interrupted, 2 if there is no
+//! terminal, and 3 on any other error.
+
+use std::{
+    env,
+    io::{self, Write},
+    process::ExitCode,
+};
+
+use readpassphrase_3::{Error, Flags, Prompt, Sanitize, with_passphrase};
+
+const CANCELLED: u8 = 1;
+const NO_TTY: u8 = 2;
+const FAILED: u8 = 3;
+
+fn main() -> ExitCode {
+    let args: Vec<_> = env::args_os()
+        .skip(1)
+        .map(|arg| arg.to_string_lossy().into_owned())
+        .collect();
+    // Prompts may span several lines, e.g. ssh's confirmation of a new host key.
+    let prompt = if args.is_empty() {
+        Prompt::new("Password: ")
+    } else {
+        Prompt::with_sanitize(args.join(" "), Sanitize::Multiline)
+    };
+    catch_signals();
+    match with_passphrase(&prompt, Flags::REQUIRE_TTY, write_passphrase) {
+        Ok(Ok(())) => ExitCode::SUCCESS,
+        Ok(Err(e)) => {
+            eprintln!("failed writing passphrase: {e}");
+            ExitCode::from(FAILED)
+        }
+        Err(Error::Io(e)) if e.kind() == io::ErrorKind::Interrupted => ExitCode::from(CANCELLED),
+        Err(Error::Io(e)) if is_no_tty(&e) => {
+            eprintln!("no terminal to read a passphrase from");
+            ExitCode::from(NO_TTY)
+        }
+        Err(e) => {
+            eprintln!("failed reading passphrase: {e}");
+            ExitCode::from(FAILED)
+        }
+    }
+}
+
+/// Writes `pass` to stdout without buffering, so that no copy of it is left behind.
+#[cfg(unix)]
+fn write_passphrase(pass: &str) -> io::Result<()> {
+    use std::{fs::File, mem::ManuallyDrop, os::fd::FromRawFd};
+
+    // SAFETY: stdout is open for the life of the process, and is not closed when this is dropped.
+    let mut stdout = ManuallyDrop::new(unsafe { File::from_raw_fd(1) });
+    stdout.write_all(pass.as_bytes())?;
+    stdout.write_all(b"\n")
+}
+
+#[cfg(not(unix))]
+fn write_passphrase(pass: &str) -> io::Result<()> {
+    let mut stdout = io::stdout().lock();
+    stdout.write_all(pass.as_bytes())?;
+    stdout.write_all(b"\n")?;
+    stdout.flush()
+}
+
+/// Makes `readpassphrase(3)` fail with `EINTR` on an interrupt, rather than re-raising it to kill
+/// the process, so that we can exit with [`CANCELLED`].
+#[cfg(unix)]
+fn catch_signals() {
+    extern "C" fn ignore(_: libc::c_int) {}
+
+    for sig in [libc::SIGHUP, libc::SIGINT, libc::SIGQUIT, libc::SIGTERM] {
+        // SAFETY: `ignore` is async-signal-safe.
+        unsafe { libc::signal(sig, ignore as *const () as libc::sighandler_t) };
+    }
+}
+
+#[cfg(not(unix))]
+fn catch_signals() {}
+
+#[cfg(unix)]
+fn is_no_tty(e: &io::Error) -> bool {
+    matches!(
+        e.raw_os_error(),
+        Some(libc::ENXIO | libc::ENOTTY | libc::ENOENT)
+    )
+}
+
+#[cfg(not(unix))]
+fn is_no_tty(_: &io::Error) -> bool {
+    false
+}
@@ -0,0 +1,358 @@
+//! Storage types that passphrases may be read into.
+
+use std::{
+    cmp, fmt,
+    mem::{ManuallyDrop, MaybeUninit},
+    ops::Deref,
+    ptr, slice, str,
+};
+
+use crate::{Error, MAX_CAPACITY, Zeroize, Zeroizing, backend};
+
+/// Writable storage for a secret, which [`readpassphrase_into`][crate::readpassphrase_into] can
+/// read a passphrase into.
+///
+/// Each implementation decides what type of passphrase it is turned into once read; this lets
+/// e.g. a [`Vec<u8>`] become a [`String`] without copying. Storage that cannot be turned into a
+/// string directly may use [`Passphrase`]:
+/// ```no_run
+/// # use readpassphrase_3::{Error, Flags, Passphrase, readpassphrase_into};
+/// # fn main() -> Result<(), Error> {
+/// let buf: Box<[u8]> = vec![0; 256].into_boxed_slice();
+/// let pass: Passphrase<Box<[u8]>> = readpassphrase_into(c"Password: ", buf, Flags::empty())?;
+/// # Ok(())
+/// # }
+/// ```
+///
+/// # Safety
+/// [`as_uninit`][SecretBuf::as_uninit] and [`as_uninit_mut`][SecretBuf::as_uninit_mut] must
+/// return the same memory, which must not change until `self` is next mutably borrowed.
+pub unsafe trait SecretBuf: Sized {
+    /// The type this storage becomes once a passphrase has been read into it.
+    type Passphrase;
+
+    /// Returns the memory that a passphrase is read into.
+    fn as_uninit(&self) -> &[MaybeUninit<u8>];
+
+    /// Returns the memory that a passphrase is read into, for writing.
+    ///
+    /// # Safety
+    /// Only initialized bytes may be written to the returned slice.
+    unsafe fn as_uninit_mut(&mut self) -> &mut [MaybeUninit<u8>];
+
+    /// Turns this into a passphrase of the first `len` bytes of its memory, returning `self` back
+    /// if they are not UTF-8.
+    ///
+    /// # Safety
+    /// The first `len` bytes of the memory must be initialized.
+    unsafe fn into_passphrase(self, len: usize)
+    -> Result<Self::Passphrase, (Self, str::Utf8Error)>;
+
+    /// Zeroes all of this storage’s memory.
+    fn wipe(&mut self);
+}
+
+/// A passphrase read into a [`SecretBuf`] of type `B`.
+///
+/// This dereferences to [`str`](prim@str), and [wipes][SecretBuf::wipe] its storage when dropped.
+pub struct Passphrase<B: SecretBuf> {
+    buf: B,
+    len: usize,
+}
+
+impl<B: SecretBuf> Passphrase<B> {
+    /// Creates a passphrase of the first `len` bytes of `buf`’s memory, returning `buf` back if
+    /// they are not UTF-8.
+    ///
+    /// This is meant for implementing [`SecretBuf::into_passphrase`].
+    ///
+    /// # Safety
+    /// The first `len` bytes of `buf`’s memory must be initialized.
+    pub unsafe fn from_buf(buf: B, len: usize) -> Result<Self, (B, str::Utf8Error)> {
+        // SAFETY: the caller guarantees that these bytes are initialized.
+        let bytes = unsafe { assume_init(&buf.as_uninit()[..len]) };
+        match str::from_utf8(bytes) {
+            Ok(_) => Ok(Passphrase { buf, len }),
+            Err(e) => Err((buf, e)),
+        }
+    }
+
+    /// Reads a passphrase into this passphrase’s storage in place with `read`, which returns the
+    /// part of the memory holding it. The storage is wiped first, and again on error.
+    pub(crate) fn read_in_place(
+        &mut self,
+        read: impl FnOnce(&mut [MaybeUninit<u8>]) -> Result<&mut [u8], Error>,
+    ) -> Result<(), Error> {
+        self.buf.wipe();
+        self.len = 0;
+        // SAFETY: `read` only writes initialized bytes, as `Backend::read_bytes` does.
+        let mem = unsafe { self.buf.as_uninit_mut() };
+        let start = mem.as_ptr();
+        let res = read(mem).and_then(|bytes| {
+            let len = backend::prefix_len(start, bytes)?;
+            str::from_utf8(bytes)?;
+            Ok(len)
+        });
+        match res {
+            Ok(len) => {
+                self.len = len;
+                Ok(())
+            }
+            Err(e) => {
+                self.buf.wipe();
+                Err(e)
+            }
+        }
+    }
+
+    /// Returns the passphrase as a <code>&[str]</code>.
+    ///
+    /// [str]: prim@str "str"
+    pub fn as_str(&self) -> &str {
+        // SAFETY: `buf[..len]` was checked to be initialized UTF-8 in `from_buf`, and `buf` has
+        // not been mutably borrowed since.
+        unsafe { str::from_utf8_unchecked(assume_init(&self.buf.as_uninit()[..self.len])) }
+    }
+
+    /// Returns the storage containing the passphrase, without wiping it.
+    ///
+    /// # Security
+    /// It is the caller’s responsibility to zero the returned storage.
+    pub fn into_inner(self) -> B {
+        let this = ManuallyDrop::new(self);
+        // SAFETY: `this` is never used or dropped again.
+        unsafe { ptr::read(&this.buf) }
+    }
+}
+
+impl<const N: usize> Passphrase<[u8; N]> {
+    /// Returns an empty passphrase, to read into with
+    /// [`readpassphrase_array_in_place`][crate::readpassphrase_array_in_place].
+    pub const fn new() -> Self {
+        Passphrase {
+            buf: [0; N],
+            len: 0,
+        }
+    }
+}
+
+impl<const N: usize> Default for Passphrase<[u8; N]> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<B: SecretBuf> Deref for Passphrase<B> {
+    type Target = str;
+
+    fn deref(&self) -> &str {
+        self.as_str()
+    }
+}
+
+impl<B: SecretBuf> AsRef<str> for Passphrase<B> {
+    fn as_ref(&self) -> &str {
+        self.as_str()
+    }
+}
+
+impl<B: SecretBuf> fmt::Debug for Passphrase<B> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Passphrase(..)")
+    }
+}
+
+impl<B: SecretBuf> Drop for Passphrase<B> {
+    fn drop(&mut self) {
+        self.buf.wipe();
+        self.len = 0;
+    }
+}
+
+/// A [`Vec`] is read into up to the larger of its length and its capacity capped at
+/// [`MAX_CAPACITY`], and becomes a [`String`] using the same allocation.
+unsafe impl SecretBuf for Vec<u8> {
+    type Passphrase = String;
+
+    fn as_uninit(&self) -> &[MaybeUninit<u8>] {
+        let len = vec_bufsiz(self);
+        // SAFETY: `len` is within the vector’s allocation.
+        unsafe { slice::from_raw_parts(self.as_ptr().cast(), len) }
+    }
+
+    unsafe fn as_uninit_mut(&mut self) -> &mut [MaybeUninit<u8>] {
+        let len = vec_bufsiz(self);
+        // SAFETY: `len` is within the vector’s allocation, and the caller only writes initialized
+        // bytes to its initialized part.
+        unsafe { slice::from_raw_parts_mut(self.as_mut_ptr().cast(), len) }
+    }
+
+    unsafe fn into_passphrase(mut self, len: usize) -> Result<String, (Self, str::Utf8Error)> {
+        // SAFETY: the caller guarantees that these bytes are initialized, and `len` is within
+        // capacity since it is within `as_uninit_mut`.
+        unsafe { self.set_len(len) };
+        String::from_utf8(self).map_err(|e| {
+            let err = e.utf8_error();
+            (e.into_bytes(), err)
+        })
+    }
+
+    fn wipe(&mut self) {
+        self.zeroize();
+    }
+}
+
+fn vec_bufsiz(buf: &Vec<u8>) -> usize {
+    cmp::max(buf.len(), cmp::min(buf.capacity(), MAX_CAPACITY))
+}
+
+unsafe impl SecretBuf for Box<[u8]> {
+    type Passphrase = Passphrase<Self>;
+
+    fn as_uninit(&self) -> &[MaybeUninit<u8>] {
+        as_uninit(self)
+    }
+
+    unsafe fn as_uninit_mut(&mut self) -> &mut [MaybeUninit<u8>] {
+        // SAFETY: the caller only writes initialized bytes.
+        unsafe { as_uninit_mut(self) }
+    }
+
+    unsafe fn into_passphrase(
+        self,
+        len: usize,
+    ) -> Result<Passphrase<Self>, (Self, str::Utf8Error)> {
+        // SAFETY: as guaranteed by the caller.
+        unsafe { Passphrase::from_buf(self, len) }
+    }
+
+    fn wipe(&mut self) {
+        self[..].zeroize();
+    }
+}
+
+unsafe impl<const N: usize> SecretBuf for [u8; N] {
+    type Passphrase = Passphrase<Self>;
+
+    fn as_uninit(&self) -> &[MaybeUninit<u8>] {
+        as_uninit(self)
+    }
+
+    unsafe fn as_uninit_mut(&mut self) -> &mut [MaybeUninit<u8>] {
+        // SAFETY: the caller only writes initialized bytes.
+        unsafe { as_uninit_mut(self) }
+    }
+
+    unsafe fn into_passphrase(
+        self,
+        len: usize,
+    ) -> Result<Passphrase<Self>, (Self, str::Utf8Error)> {
+        // SAFETY: as guaranteed by the caller.
+        unsafe { Passphrase::from_buf(self, len) }
+    }
+
+    fn wipe(&mut self) {
+        self[..].zeroize();
+    }
+}
+
+/// A [`Zeroizing`] vector is read into like a [`Vec`], and becomes a `Zeroizing` [`String`].
+unsafe impl SecretBuf for Zeroizing<Vec<u8>> {
+    type Passphrase = Zeroizing<String>;
+
+    fn as_uninit(&self) -> &[MaybeUninit<u8>] {
+        (**self).as_uninit()
+    }
+
+    unsafe fn as_uninit_mut(&mut self) -> &mut [MaybeUninit<u8>] {
+        // SAFETY: as guaranteed by the caller.
+        unsafe { (**self).as_uninit_mut() }
+    }
+
+    unsafe fn into_passphrase(
+        mut self,
+        len: usize,
+    ) -> Result<Self::Passphrase, (Self, str::Utf8Error)> {
+        // SAFETY: as guaranteed by the caller.
+        match unsafe { std::mem::take(&mut *self).into_passphrase(len) } {
+            Ok(s) => Ok(Zeroizing::new(s)),
+            Err((buf, e)) => Err((Zeroizing::new(buf), e)),
+        }
+    }
+
+    fn wipe(&mut self) {
+        (**self).zeroize();
+    }
+}
+
+#[cfg(feature = "secrecy")]
+unsafe impl SecretBuf for secrecy::SecretBox<[u8]> {
+    type Passphrase = Passphrase<Self>;
+
+    fn as_uninit(&self) -> &[MaybeUninit<u8>] {
+        as_uninit(secrecy::ExposeSecret::expose_secret(self))
+    }
+
+    unsafe fn as_uninit_mut(&mut self) -> &mut [MaybeUninit<u8>] {
+        // SAFETY: the caller only writes initialized bytes.
+        unsafe { as_uninit_mut(secrecy::ExposeSecretMut::expose_secret_mut(self)) }
+    }
+
+    unsafe fn into_passphrase(
+        self,
+        len: usize,
+    ) -> Result<Passphrase<Self>, (Self, str::Utf8Error)> {
+        // SAFETY: as guaranteed by the caller.
+        unsafe { Passphrase::from_buf(self, len) }
+    }
+
+    fn wipe(&mut self) {
+        secrecy::zeroize::Zeroize::zeroize(self);
+    }
+}
+
+fn as_uninit(buf: &[u8]) -> &[MaybeUninit<u8>] {
+    // SAFETY: `MaybeUninit<u8>` has the same layout as `u8`, and the result cannot be written to.
+    unsafe { &*(ptr::from_ref(buf) as *const [MaybeUninit<u8>]) }
+}
+
+/// # Safety
+/// Only initialized bytes may be written to the result.
+unsafe fn as_uninit_mut(buf: &mut [u8]) -> &mut [MaybeUninit<u8>] {
+    // SAFETY: `MaybeUninit<u8>` has the same layout as `u8`.
+    unsafe { &mut *(ptr::from_mut(buf) as *mut [MaybeUninit<u8>]) }
+}
+
+/// # Safety
+/// `buf` must be initialized.
+unsafe fn assume_init(buf: &[MaybeUninit<u8>]) -> &[u8] {
+    // SAFETY: as guaranteed by the caller.
+    unsafe { &*(ptr::from_ref(buf) as *const [u8]) }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_into_passphrase() {
+        let mut buf = Vec::with_capacity(8);
+        buf.extend_from_slice(b"xy");
+        let mem = unsafe { buf.as_uninit_mut() };
+        assert_eq!(8, mem.len());
+        mem[..4].copy_from_slice(&b"pass".map(MaybeUninit::new));
+        let ptr = buf.as_ptr();
+        let pass = unsafe { buf.into_passphrase(3) }.unwrap();
+        assert_eq!("pas", pass);
+        assert_eq!(ptr, pass.as_ptr());
+
+        let buf = Box::<[u8]>::from(*b"\xffbc");
+        let (buf, _) = unsafe { buf.into_passphrase(2) }.unwrap_err();
+        let pass = unsafe { buf.into_passphrase(0) }.unwrap();
+        assert_eq!("", &*pass);
+        assert_eq!(b"\xffbc", &*pass.into_inner());
+
+        let pass = unsafe { (*b"abc").into_passphrase(2) }.unwrap();
+        assert_eq!("ab", &*pass);
+    }
+}
@@ -0,0 +1,156 @@
+//! Integration with [`clap`].
+
+use std::{
+    ffi::CStr,
+    fmt,
+    io::{self, IsTerminal},
+    ops::Deref,
+};
+
+use clap::{ArgMatches, error::ErrorKind};
+
+use crate::{Flags, PASSWORD_LEN, Zeroizing, readpassphrase_into};
+
+/// A secret command-line argument, which is prompted for if it was not given.
+///
+/// A `SecretArg` may be parsed by [`clap`] like a [`String`]. Once arguments have been parsed,
+/// [`SecretArg::remove_or_prompt`] or [`SecretArg::or_prompt`] prompts for a missing one:
+/// ```no_run
+/// # use clap::{Arg, Command, value_parser};
+/// # use readpassphrase_3::{Flags, SecretArg};
+/// let mut matches = Command::new("app")
+///     .arg(Arg::new("password").long("password").value_parser(value_parser!(SecretArg)))
+///     .get_matches();
+/// let password = SecretArg::remove_or_prompt(
+///     &mut matches,
+///     "password",
+///     c"Password: ",
+///     Flags::REQUIRE_TTY,
+/// )
+/// .unwrap_or_else(|e| e.exit());
+/// ```
+///
+/// The secret is zeroed when dropped. Note however that an argument given on the command line is
+/// also kept by `clap` and the OS, and is visible to other processes on most systems; prompting
+/// is preferable.
+#[derive(Clone)]
+pub struct SecretArg(Zeroizing<String>);
+
+impl SecretArg {
+    /// Returns `value`, or if it is [`None`], prompts for the argument `arg` with `prompt`.
+    ///
+    /// This is meant for arguments parsed with the derive API, e.g. from a field
+    /// `password: Option<SecretArg>`.
+    ///
+    /// # Errors
+    /// Returns a [`clap::Error`] if `value` is [`None`] and stdin is not a terminal, or if reading
+    /// the passphrase failed, e.g. because [`Flags::REQUIRE_TTY`] was given and there is no tty.
+    pub fn or_prompt(
+        value: Option<SecretArg>,
+        arg: &str,
+        prompt: &CStr,
+        flags: Flags,
+    ) -> Result<SecretArg, clap::Error> {
+        or_prompt(value, arg, prompt, flags, io::stdin().is_terminal())
+    }
+
+    /// Removes the argument with id `id` from `matches`, or prompts for it with `prompt` if it was
+    /// not given.
+    ///
+    /// # Errors
+    /// As with [`SecretArg::or_prompt`].
+    ///
+    /// # Panics
+    /// As with [`ArgMatches::remove_one`], if `id` is not a `SecretArg` argument.
+    pub fn remove_or_prompt(
+        matches: &mut ArgMatches,
+        id: &str,
+        prompt: &CStr,
+        flags: Flags,
+    ) -> Result<SecretArg, clap::Error> {
+        SecretArg::or_prompt(matches.remove_one(id), id, prompt, flags)
+    }
+
+    /// Returns the secret as a <code>&[str]</code>.
+    ///
+    /// [str]: prim@str "str"
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+fn or_prompt(
+    value: Option<SecretArg>,
+    arg: &str,
+    prompt: &CStr,
+    flags: Flags,
+    is_terminal: bool,
+) -> Result<SecretArg, clap::Error> {
+    if let Some(value) = value {
+        return Ok(value);
+    }
+    if !is_terminal {
+        return Err(clap::Error::raw(
+            ErrorKind::MissingRequiredArgument,
+            format!("`{arg}` was not given, and stdin is not a terminal to prompt for it\n"),
+        ));
+    }
+    match readpassphrase_into(prompt, Vec::with_capacity(PASSWORD_LEN), flags) {
+        Ok(pass) => Ok(SecretArg(Zeroizing::new(pass))),
+        Err(e) => Err(clap::Error::raw(
+            ErrorKind::Io,
+            format!("failed to read `{arg}`: {}\n", e.error()),
+        )),
+    }
+}
+
+impl From<String> for SecretArg {
+    fn from(value: String) -> Self {
+        SecretArg(Zeroizing::new(value))
+    }
+}
+
+impl Deref for SecretArg {
+    type Target = str;
+
+    fn deref(&self) -> &str {
+        self.as_str()
+    }
+}
+
+impl AsRef<str> for SecretArg {
+    fn as_ref(&self) -> &str {
+        self.as_str()
+    }
+}
+
+impl fmt::Debug for SecretArg {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("SecretArg(..)")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use clap::{Arg, Command, value_parser};
+
+    use super::*;
+
+    #[test]
+    fn test_or_prompt() {
+        let cmd = Command::new("test").arg(
+            Arg::new("password")
+                .long("password")
+                .value_parser(value_parser!(SecretArg)),
+        );
+        let mut matches = cmd
+            .try_get_matches_from(["test", "--password", "pass"])
+            .unwrap();
+        let value = matches.remove_one("password");
+        let pass = or_prompt(value, "password", c"", Flags::empty(), false).unwrap();
+        assert_eq!("pass", &*pass);
+
+        let err = or_prompt(None, "password", c"", Flags::empty(), false).unwrap_err();
+        assert_eq!(ErrorKind::MissingRequiredArgument, err.kind());
+    }
+}
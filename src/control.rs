@@ -0,0 +1,98 @@
+//! Control character filtering.
+
+use crate::{Control, Error, Zeroize};
+
+/// Applies `control` to the NUL-terminated passphrase of `len` bytes in `buf`, returning its new
+/// length.
+pub(crate) fn filter(buf: &mut [u8], len: usize, control: Control) -> Result<usize, Error> {
+    if control == Control::Allow {
+        return Ok(len);
+    }
+    let (mut r, mut w) = (0, 0);
+    while r < len {
+        let n = control_len(&buf[r..len]);
+        if n == 0 {
+            buf[w] = buf[r];
+            w += 1;
+            r += 1;
+            continue;
+        }
+        if control == Control::Reject {
+            buf.zeroize();
+            return Err(Error::Control);
+        }
+        r += n;
+    }
+    buf[w..=len].zeroize();
+    Ok(w)
+}
+
+/// Returns the length of the control character or escape sequence at the start of `s`, or 0 if
+/// `s` does not start with one.
+fn control_len(s: &[u8]) -> usize {
+    match s {
+        // CSI, either as ESC [ or as U+009B.
+        [0x1b, b'[', rest @ ..] => 2 + csi_len(rest),
+        [0xc2, 0x9b, rest @ ..] => 2 + csi_len(rest),
+        // OSC, DCS, PM and APC, terminated by BEL or ST.
+        [0x1b, b']' | b'P' | b'^' | b'_', rest @ ..] => 2 + string_len(rest),
+        // SS3, as sent by some function keys.
+        [0x1b, b'O', 0x20..=0x7e, ..] => 3,
+        [0x1b, 0x20..=0x7e, ..] => 2,
+        [0x00..=0x1f | 0x7f, ..] => 1,
+        // Other C1 controls, U+0080 through U+009F.
+        [0xc2, 0x80..=0x9f, ..] => 2,
+        _ => 0,
+    }
+}
+
+/// Returns the length of the parameters and final byte of a CSI sequence.
+fn csi_len(s: &[u8]) -> usize {
+    match s.iter().position(|b| (0x40..=0x7e).contains(b)) {
+        Some(i) => i + 1,
+        None => s.len(),
+    }
+}
+
+/// Returns the length of a control string and its terminator.
+fn string_len(s: &[u8]) -> usize {
+    for i in 0..s.len() {
+        match s[i..] {
+            [0x07, ..] => return i + 1,
+            [0x1b, b'\\', ..] => return i + 2,
+            _ => {}
+        }
+    }
+    s.len()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn filtered(input: &[u8], control: Control) -> Result<Vec<u8>, Error> {
+        let mut buf = input.to_vec();
+        buf.push(0);
+        let len = filter(&mut buf, input.len(), control)?;
+        assert!(buf[len..].iter().all(|&b| b == 0));
+        buf.truncate(len);
+        Ok(buf)
+    }
+
+    #[test]
+    fn test_filter() {
+        let input =
+            "a\x1b[31mb\tc\u{9b}1;2Hd\x1b]0;title\x07e\x1b]0;x\x1b\\f\x1bOPg\u{85}ü".as_bytes();
+        assert_eq!(input, filtered(input, Control::Allow).unwrap());
+        assert_eq!(
+            "abcdefgü".as_bytes(),
+            filtered(input, Control::Strip).unwrap()
+        );
+        assert!(matches!(
+            filtered(input, Control::Reject),
+            Err(Error::Control)
+        ));
+        assert_eq!(b"abc", &*filtered(b"abc", Control::Reject).unwrap());
+        assert_eq!(b"ab", &*filtered(b"ab\x1b[", Control::Strip).unwrap());
+    }
+}
@@ -0,0 +1,248 @@
+//! The `git credential` helper protocol.
+
+use std::{
+    fmt,
+    io::{self, Read, Write},
+    str,
+};
+
+use crate::{Error, Flags, Options, PASSWORD_LEN, Prompt, Zeroizing};
+
+/// The attributes of a credential, as passed between `git` and a [credential helper][0].
+///
+/// Attributes are read from and written as lines of `key=value`. All values are kept in
+/// [`Zeroizing`] storage, as any of them may be secret.
+///
+/// A credential helper built on this reads the credential git is asking for, fills in any missing
+/// username and password, and writes it back:
+/// ```no_run
+/// # use std::io;
+/// # use readpassphrase_3::{Error, GitCredential, Options};
+/// # fn main() -> Result<(), Error> {
+/// if std::env::args().nth(1).as_deref() == Some("get") {
+///     let mut credential = GitCredential::read_from(io::stdin().lock())?;
+///     credential.fill(&Options::default())?;
+///     credential.write_to(io::stdout().lock())?;
+/// }
+/// # Ok(())
+/// # }
+/// ```
+///
+/// [0]: https://git-scm.com/docs/gitcredentials
+#[derive(Clone, Default, PartialEq, Eq)]
+pub struct GitCredential {
+    attrs: Vec<(String, Zeroizing<String>)>,
+}
+
+impl GitCredential {
+    /// Reads a credential from `reader`, up to a blank line or the end of the input.
+    ///
+    /// `reader` is read a byte at a time, so that nothing after the blank line is consumed. Note
+    /// that a buffer within `reader` itself, such as that of [`io::Stdin`], is not zeroed.
+    ///
+    /// # Errors
+    /// Returns [`Error::Io`] if reading fails or a line is not of the form `key=value`, and
+    /// [`Error::Utf8`] if a line is not UTF-8.
+    pub fn read_from(mut reader: impl Read) -> Result<Self, Error> {
+        let mut credential = GitCredential::default();
+        let mut line = Zeroizing::new(Vec::with_capacity(PASSWORD_LEN));
+        loop {
+            line.clear();
+            let eof = loop {
+                let mut b = 0;
+                match reader.read(std::slice::from_mut(&mut b)) {
+                    Ok(0) => break true,
+                    Ok(_) if b == b'\n' => break false,
+                    Ok(_) => push(&mut line, b),
+                    Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                    Err(e) => return Err(e.into()),
+                }
+            };
+            if line.is_empty() {
+                return Ok(credential);
+            }
+            let (key, value) = str::from_utf8(&line)?
+                .split_once('=')
+                .ok_or_else(|| invalid("credential line is not `key=value`"))?;
+            credential
+                .attrs
+                .push((key.to_string(), Zeroizing::new(value.to_string())));
+            if eof {
+                return Ok(credential);
+            }
+        }
+    }
+
+    /// Writes the credential to `writer`, as lines of `key=value` followed by a blank line.
+    ///
+    /// # Errors
+    /// Returns [`Err`] if writing fails, or if a key or value contains a newline or NUL, or a key
+    /// contains `=`.
+    pub fn write_to(&self, mut writer: impl Write) -> io::Result<()> {
+        let len = self
+            .attrs
+            .iter()
+            .map(|(k, v)| k.len() + v.len() + 2)
+            .sum::<usize>();
+        let mut out = Zeroizing::new(Vec::with_capacity(len + 1));
+        for (key, value) in &self.attrs {
+            if key.contains(['=', '\n', '\0']) || value.contains(['\n', '\0']) {
+                return Err(invalid(
+                    "credential attribute contains a reserved character",
+                ));
+            }
+            out.extend_from_slice(key.as_bytes());
+            out.push(b'=');
+            out.extend_from_slice(value.as_bytes());
+            out.push(b'\n');
+        }
+        out.push(b'\n');
+        writer.write_all(&out)?;
+        writer.flush()
+    }
+
+    /// Returns the first value of `key`.
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.attrs
+            .iter()
+            .find(|(k, _)| k == key)
+            .map(|(_, v)| v.as_str())
+    }
+
+    /// Returns every value of `key`, e.g. of a multi-valued key such as `capability[]`.
+    pub fn get_all<'a>(&'a self, key: &'a str) -> impl Iterator<Item = &'a str> {
+        self.attrs
+            .iter()
+            .filter(move |(k, _)| k == key)
+            .map(|(_, v)| v.as_str())
+    }
+
+    /// Sets `key` to `value`, replacing its first value if it has one.
+    pub fn set(&mut self, key: &str, value: impl Into<Zeroizing<String>>) {
+        let value = value.into();
+        match self.attrs.iter_mut().find(|(k, _)| k == key) {
+            Some((_, v)) => *v = value,
+            None => self.attrs.push((key.to_string(), value)),
+        }
+    }
+
+    /// Returns the `username` attribute.
+    pub fn username(&self) -> Option<&str> {
+        self.get("username")
+    }
+
+    /// Returns the `password` attribute.
+    pub fn password(&self) -> Option<&str> {
+        self.get("password")
+    }
+
+    /// Prompts for the `username` and `password` attributes, if they are missing.
+    ///
+    /// The username is read with [`Flags::ECHO_ON`] added to `options`, and the password with
+    /// `options` as given. [`Flags::REQUIRE_TTY`] is always added, as stdin is used by the
+    /// protocol. The prompts name the URL being accessed, as git’s own do.
+    ///
+    /// # Errors
+    /// As with [`Options::readpassphrase_into`].
+    pub fn fill(&mut self, options: &Options) -> Result<(), Error> {
+        let mut options = *options;
+        options.flags |= Flags::REQUIRE_TTY;
+        if self.username().is_none() {
+            let options = Options {
+                flags: options.flags | Flags::ECHO_ON,
+                ..options
+            };
+            let prompt = Prompt::new(format!("Username for '{}': ", self.url(false)));
+            let buf = Zeroizing::new(Vec::with_capacity(PASSWORD_LEN));
+            let username = options.readpassphrase_into(&prompt, buf)?;
+            self.set("username", username);
+        }
+        if self.password().is_none() {
+            let prompt = Prompt::new(format!("Password for '{}': ", self.url(true)));
+            let buf = Zeroizing::new(Vec::with_capacity(PASSWORD_LEN));
+            let password = options.readpassphrase_into(&prompt, buf)?;
+            self.set("password", password);
+        }
+        Ok(())
+    }
+
+    /// Returns the URL this credential is for, with the username if `with_username` is set.
+    fn url(&self, with_username: bool) -> String {
+        if let Some(url) = self.get("url") {
+            return url.to_string();
+        }
+        let mut url = String::new();
+        if let Some(protocol) = self.get("protocol") {
+            url = format!("{protocol}://");
+        }
+        if let Some(username) = self.username().filter(|_| with_username) {
+            url = format!("{url}{username}@");
+        }
+        url.push_str(self.get("host").unwrap_or_default());
+        if let Some(path) = self.get("path") {
+            url = format!("{url}/{path}");
+        }
+        url
+    }
+}
+
+impl fmt::Debug for GitCredential {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_list()
+            .entries(self.attrs.iter().map(|(k, _)| k))
+            .finish()
+    }
+}
+
+/// Pushes `b` onto `line`, zeroing the old allocation if it has to grow.
+fn push(line: &mut Zeroizing<Vec<u8>>, b: u8) {
+    if line.len() == line.capacity() {
+        let mut grown = Zeroizing::new(Vec::with_capacity(line.capacity().max(1) * 2));
+        grown.extend_from_slice(line);
+        *line = grown;
+    }
+    line.push(b);
+}
+
+fn invalid(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_git_credential() {
+        let input = b"protocol=https\nhost=example.com\nwwwauth[]=Basic\nwwwauth[]=Bearer\n\nrest";
+        let mut reader = &input[..];
+        let mut credential = GitCredential::read_from(&mut reader).unwrap();
+        assert_eq!(b"rest", reader);
+        assert_eq!(Some("example.com"), credential.get("host"));
+        assert_eq!(None, credential.username());
+        let wwwauth: Vec<_> = credential.get_all("wwwauth[]").collect();
+        assert_eq!(["Basic", "Bearer"], &wwwauth[..]);
+        assert_eq!("https://example.com", credential.url(true));
+
+        credential.set("username", "user".to_string());
+        credential.set("password", "pass".to_string());
+        credential.fill(&Options::default()).unwrap();
+        assert_eq!("https://user@example.com", credential.url(true));
+        let mut out = Vec::new();
+        credential.write_to(&mut out).unwrap();
+        assert_eq!(
+            &b"protocol=https\nhost=example.com\nwwwauth[]=Basic\nwwwauth[]=Bearer\nusername=user\npassword=pass\n\n"[..],
+            out
+        );
+
+        credential.set("password", "a\nb".to_string());
+        assert!(credential.write_to(io::sink()).is_err());
+        assert!(GitCredential::read_from(&b"host"[..]).is_err());
+        assert_eq!(
+            Some("x"),
+            GitCredential::read_from(&b"host=x"[..])
+                .unwrap()
+                .get("host")
+        );
+    }
+}
@@ -0,0 +1,222 @@
+//! Process hardening while secrets are live.
+
+use std::{
+    io,
+    ops::{Deref, DerefMut},
+    sync::{Mutex, MutexGuard},
+};
+
+/// A guard that keeps the process from dumping core or being attached to while it is live.
+///
+/// While any `Hardening` exists, the soft `RLIMIT_CORE` is set to 0, and on Linux, the process is
+/// made non-dumpable with `PR_SET_DUMPABLE`. The latter also prevents other unprivileged processes
+/// from attaching to this one with `ptrace(2)` or reading its memory through `/proc`. Both are
+/// restored to their previous values once the last `Hardening` is dropped:
+/// ```no_run
+/// # use readpassphrase_3::{Error, Hardening, getpass};
+/// # fn main() -> Result<(), Error> {
+/// let hardening = Hardening::new()?;
+/// let pass = getpass(c"Password: ")?;
+/// // ...
+/// drop(pass);
+/// drop(hardening);
+/// # Ok(())
+/// # }
+/// ```
+///
+/// See [`Hardened`] to tie hardening to the lifetime of a returned secret.
+///
+/// # Side effects
+/// A non-dumpable process’s `/proc/<pid>` entries are owned by root, so e.g. the process may not
+/// be able to open its own `/proc/self/mem` for the duration.
+#[derive(Debug)]
+pub struct Hardening(());
+
+/// A secret kept together with a [`Hardening`], so that the process stays hardened as long as
+/// the secret is live.
+///
+/// This dereferences to the secret:
+/// ```no_run
+/// # use readpassphrase_3::{Error, Hardened, getpass};
+/// # fn main() -> Result<(), Error> {
+/// let pass = Hardened::new(getpass(c"Password: ")?)?;
+/// let pass: &str = &pass;
+/// # Ok(())
+/// # }
+/// ```
+///
+/// Note that the process is only hardened once the secret has been read; use a [`Hardening`]
+/// directly to cover the time spent reading it as well.
+#[derive(Debug)]
+pub struct Hardened<T> {
+    secret: T,
+    _hardening: Hardening,
+}
+
+/// The process state to restore once the last [`Hardening`] is dropped.
+struct Saved {
+    count: usize,
+    core: libc::rlimit,
+    dumpable: libc::c_int,
+}
+
+static SAVED: Mutex<Saved> = Mutex::new(Saved {
+    count: 0,
+    core: libc::rlimit {
+        rlim_cur: 0,
+        rlim_max: 0,
+    },
+    dumpable: 0,
+});
+
+impl Hardening {
+    /// Hardens the process until the returned guard and any others are dropped.
+    ///
+    /// # Errors
+    /// Returns [`Err`] if the process state could not be read or changed. In this case, any
+    /// changes are reverted.
+    pub fn new() -> io::Result<Hardening> {
+        let mut saved = lock();
+        if saved.count == 0 {
+            let core = get_core_limit()?;
+            let dumpable = imp::get_dumpable()?;
+            set_core_limit(libc::rlimit {
+                rlim_cur: 0,
+                ..core
+            })?;
+            if let Err(e) = imp::set_dumpable(0) {
+                _ = set_core_limit(core);
+                return Err(e);
+            }
+            saved.core = core;
+            saved.dumpable = dumpable;
+        }
+        saved.count += 1;
+        Ok(Hardening(()))
+    }
+}
+
+impl Drop for Hardening {
+    fn drop(&mut self) {
+        let mut saved = lock();
+        saved.count -= 1;
+        if saved.count == 0 {
+            _ = imp::set_dumpable(saved.dumpable);
+            _ = set_core_limit(saved.core);
+        }
+    }
+}
+
+impl<T> Hardened<T> {
+    /// Hardens the process for as long as `secret` is kept in the returned value.
+    ///
+    /// # Errors
+    /// As with [`Hardening::new`].
+    pub fn new(secret: T) -> io::Result<Hardened<T>> {
+        Ok(Hardened {
+            secret,
+            _hardening: Hardening::new()?,
+        })
+    }
+
+    /// Returns the secret, ending this value’s hardening.
+    pub fn into_inner(self) -> T {
+        self.secret
+    }
+}
+
+impl<T> Deref for Hardened<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.secret
+    }
+}
+
+impl<T> DerefMut for Hardened<T> {
+    fn deref_mut(&mut self) -> &mut T {
+        &mut self.secret
+    }
+}
+
+fn lock() -> MutexGuard<'static, Saved> {
+    SAVED.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+fn get_core_limit() -> io::Result<libc::rlimit> {
+    let mut limit = libc::rlimit {
+        rlim_cur: 0,
+        rlim_max: 0,
+    };
+    // SAFETY: `getrlimit` writes an `rlimit` to its pointer argument.
+    if unsafe { libc::getrlimit(libc::RLIMIT_CORE, &mut limit) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(limit)
+}
+
+fn set_core_limit(limit: libc::rlimit) -> io::Result<()> {
+    // SAFETY: `setrlimit` reads an `rlimit` from its pointer argument.
+    if unsafe { libc::setrlimit(libc::RLIMIT_CORE, &limit) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+mod imp {
+    use std::io;
+
+    pub(super) fn get_dumpable() -> io::Result<libc::c_int> {
+        // SAFETY: `PR_GET_DUMPABLE` takes no further arguments and has no effect on memory.
+        let res = unsafe { libc::prctl(libc::PR_GET_DUMPABLE) };
+        if res < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(res)
+    }
+
+    pub(super) fn set_dumpable(dumpable: libc::c_int) -> io::Result<()> {
+        let arg = libc::c_ulong::try_from(dumpable).unwrap_or(0);
+        // SAFETY: `PR_SET_DUMPABLE` takes an integer and has no effect on memory.
+        if unsafe { libc::prctl(libc::PR_SET_DUMPABLE, arg) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "android")))]
+mod imp {
+    use std::io;
+
+    pub(super) fn get_dumpable() -> io::Result<libc::c_int> {
+        Ok(0)
+    }
+
+    pub(super) fn set_dumpable(_dumpable: libc::c_int) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_hardening() {
+        let core = get_core_limit().unwrap();
+        let dumpable = imp::get_dumpable().unwrap();
+        let outer = Hardening::new().unwrap();
+        let inner = Hardened::new("secret").unwrap();
+        assert_eq!(0, get_core_limit().unwrap().rlim_cur);
+        assert_eq!(core.rlim_max, get_core_limit().unwrap().rlim_max);
+        assert_eq!(0, imp::get_dumpable().unwrap());
+        drop(outer);
+        assert_eq!(0, get_core_limit().unwrap().rlim_cur);
+        assert_eq!(0, imp::get_dumpable().unwrap());
+        assert_eq!("secret", inner.into_inner());
+        assert_eq!(core.rlim_cur, get_core_limit().unwrap().rlim_cur);
+        assert_eq!(dumpable, imp::get_dumpable().unwrap());
+    }
+}
@@ -0,0 +1,237 @@
+//! Caching passphrases in the Linux kernel keyring.
+
+use std::{
+    ffi::{CStr, CString, c_long},
+    io,
+    mem::MaybeUninit,
+    time::Duration,
+};
+
+use crate::{Backend, Error, Options, backend};
+
+/// A `key_serial_t`, the ID of a key or keyring.
+type KeySerial = i32;
+
+/// The key type used for cached passphrases.
+const KEY_TYPE: &CStr = c"user";
+
+/// A kernel keyring to cache passphrases in; see [`keyrings(7)`][0].
+///
+/// [0]: https://man7.org/linux/man-pages/man7/keyrings.7.html
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum Keyring {
+    /// The session keyring, shared by the processes of e.g. a login session.
+    #[default]
+    Session,
+    /// The user keyring, shared by all of the user’s processes.
+    User,
+}
+
+/// A [`Backend`] that caches passphrases read by another backend in the kernel keyring.
+///
+/// The first read prompts using [`backend`][Self::backend], and stores the passphrase as a key
+/// of type `user` named [`description`][Self::description]. Later reads with the same description
+/// return the stored passphrase without prompting, until it times out or is invalidated:
+/// ```no_run
+/// # use std::time::Duration;
+/// # use readpassphrase_3::{Backend, Error, KeyringCache, Options};
+/// # fn check(_: &str) -> bool { true }
+/// # fn main() -> Result<(), Error> {
+/// let mut cache = KeyringCache::new("myapp:unlock", Options::default());
+/// cache.timeout = Some(Duration::from_secs(300));
+/// let pass = cache.getpass(c"Unlock passphrase: ")?;
+/// if !check(&pass) {
+///     cache.invalidate()?;
+/// }
+/// # Ok(())
+/// # }
+/// ```
+///
+/// Any passphrase that is read successfully is cached, so if it turns out to be wrong, call
+/// [`KeyringCache::invalidate`] so that the next read prompts again. If the process has no session
+/// keyring, the user session keyring is used instead, without creating a new session keyring. If
+/// the keyring cannot be used at all, e.g. because keyrings are not supported, every read prompts.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct KeyringCache<B = Options> {
+    /// The description to store the passphrase under.
+    pub description: String,
+    /// The keyring to store the passphrase in.
+    pub keyring: Keyring,
+    /// How long the passphrase is kept for. If this is [`None`], it is kept until the keyring
+    /// goes away, e.g. at the end of the session.
+    pub timeout: Option<Duration>,
+    /// The backend to read the passphrase with when it is not cached.
+    pub backend: B,
+}
+
+impl<B: Backend> KeyringCache<B> {
+    /// Returns a cache of passphrases read with `backend`, stored under `description` in the
+    /// session keyring with no timeout.
+    pub fn new(description: impl Into<String>, backend: B) -> Self {
+        KeyringCache {
+            description: description.into(),
+            keyring: Keyring::default(),
+            timeout: None,
+            backend,
+        }
+    }
+
+    /// Removes the cached passphrase, if there is one, so that the next read prompts again.
+    ///
+    /// # Errors
+    /// Returns [`Error::Io`] if the key could not be searched for or invalidated.
+    pub fn invalidate(&self) -> Result<(), Error> {
+        if let Some(key) = self.search()? {
+            keyctl(libc::KEYCTL_INVALIDATE, key as _, 0, 0)?;
+        }
+        Ok(())
+    }
+
+    /// Returns the serial number of the cached key, if there is one.
+    fn search(&self) -> io::Result<Option<KeySerial>> {
+        let description = self.c_description()?;
+        let res = keyctl(
+            libc::KEYCTL_SEARCH,
+            self.keyring.id() as _,
+            KEY_TYPE.as_ptr() as _,
+            description.as_ptr() as _,
+        );
+        match res {
+            Ok(key) => Ok(Some(key as _)),
+            Err(e) if is_missing(&e) => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Reads the cached passphrase into `buf`, returning its full length if there is one. Only
+    /// `buf.len() - 1` bytes are read if it is longer.
+    fn read_cached(&self, buf: &mut [u8]) -> io::Result<Option<usize>> {
+        let Some(key) = self.search()? else {
+            return Ok(None);
+        };
+        let cap = buf.len().saturating_sub(1);
+        match keyctl(libc::KEYCTL_READ, key as _, buf.as_mut_ptr() as _, cap as _) {
+            Ok(len) => Ok(Some(len as usize)),
+            Err(e) if is_missing(&e) => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Stores `pass` in the keyring.
+    fn store(&self, pass: &[u8]) -> io::Result<()> {
+        let description = self.c_description()?;
+        // Look up the keyring without creating it, as `add_key(2)` would otherwise join a new
+        // anonymous session keyring when the process has none. In that case this returns the
+        // user session keyring, which is also the one searched by `read_cached`.
+        let keyring = keyctl(libc::KEYCTL_GET_KEYRING_ID, self.keyring.id() as _, 0, 0)?;
+        // SAFETY: the strings are NUL-terminated, and `pass` is readable for its length.
+        let key = unsafe {
+            libc::syscall(
+                libc::SYS_add_key,
+                KEY_TYPE.as_ptr(),
+                description.as_ptr(),
+                pass.as_ptr(),
+                pass.len(),
+                keyring as KeySerial,
+            )
+        };
+        if key == -1 {
+            return Err(io::Error::last_os_error());
+        }
+        if let Some(timeout) = self.timeout {
+            let secs = timeout.as_secs().clamp(1, u32::MAX.into());
+            keyctl(libc::KEYCTL_SET_TIMEOUT, key as _, secs as _, 0)?;
+        }
+        Ok(())
+    }
+
+    fn c_description(&self) -> io::Result<CString> {
+        CString::new(self.description.as_str()).map_err(io::Error::from)
+    }
+}
+
+impl<B: Backend> Backend for KeyringCache<B> {
+    fn read_bytes<'a>(
+        &self,
+        prompt: &CStr,
+        buf: &'a mut [MaybeUninit<u8>],
+    ) -> Result<&'a mut [u8], Error> {
+        backend::read_cached(
+            &self.backend,
+            prompt,
+            buf,
+            |buf| self.read_cached(buf),
+            |pass| self.store(pass),
+        )
+    }
+}
+
+impl Keyring {
+    /// Returns the special keyring ID for this keyring.
+    fn id(self) -> KeySerial {
+        match self {
+            Keyring::Session => libc::KEY_SPEC_SESSION_KEYRING,
+            Keyring::User => libc::KEY_SPEC_USER_KEYRING,
+        }
+    }
+}
+
+/// Calls `keyctl(2)` with `operation` and its arguments. The last argument, which only some
+/// operations take, is passed as zero.
+fn keyctl(operation: u32, arg2: c_long, arg3: c_long, arg4: c_long) -> io::Result<c_long> {
+    // SAFETY: the callers pass valid arguments for `operation`.
+    let res = unsafe { libc::syscall(libc::SYS_keyctl, operation, arg2, arg3, arg4, 0) };
+    if res == -1 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(res)
+}
+
+/// Returns whether `e` means that there is no usable key.
+fn is_missing(e: &io::Error) -> bool {
+    matches!(
+        e.raw_os_error(),
+        Some(libc::ENOKEY | libc::EKEYEXPIRED | libc::EKEYREVOKED)
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use std::process;
+
+    use super::*;
+    use crate::AskPass;
+
+    #[test]
+    fn test_keyring_cache() {
+        // Keyrings may be unsupported, or blocked e.g. by a container's seccomp filter.
+        let probe = keyctl(
+            libc::KEYCTL_GET_KEYRING_ID,
+            Keyring::Session.id() as _,
+            0,
+            0,
+        );
+        if let Err(e) = probe {
+            if matches!(e.raw_os_error(), Some(libc::ENOSYS | libc::EPERM)) {
+                eprintln!("skipping: keyrings are unavailable: {e}");
+                return;
+            }
+            panic!("{e}");
+        }
+        let description = format!("readpassphrase-test:{}", process::id());
+        let cache = KeyringCache::new(description, AskPass::new("echo"));
+        cache.invalidate().unwrap();
+        assert_eq!("first", cache.getpass(c"first").unwrap());
+        assert_eq!("first", cache.getpass(c"second").unwrap());
+        let mut buf = [0u8; 4];
+        assert_eq!("thi", cache.readpassphrase(c"third", &mut buf).unwrap());
+        cache.invalidate().unwrap();
+        assert_eq!("fourth", cache.getpass(c"fourth").unwrap());
+        cache.invalidate().unwrap();
+
+        // A description that cannot be stored leaves the passphrase uncached.
+        let cache = KeyringCache::new("readpassphrase-test:\0", AskPass::new("echo"));
+        assert_eq!("fifth", cache.getpass(c"fifth").unwrap());
+        assert_eq!("sixth", cache.getpass(c"sixth").unwrap());
+    }
+}
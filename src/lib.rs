@@ -31,7 +31,15 @@
 //! > The calling process should zero the passphrase as soon as possible to avoid leaving the
 //! > cleartext passphrase visible in the process's address space.
 //!
-//! It is your job to ensure that this is done with the data you own, i.e.
+//! The simplest way to do this is to not own the data at all: [`with_passphrase`] lends the
+//! passphrase to a closure, and zeroes it afterwards however the closure exits:
+//! ```no_run
+//! # use readpassphrase_3::{Flags, with_passphrase};
+//! # fn login(_: &str) {}
+//! with_passphrase(c"password: ", Flags::empty(), |pass| login(pass)).unwrap();
+//! ```
+//!
+//! Otherwise, it is your job to ensure that this is done with the data you own, i.e.
 //! any [`Vec`] passed to [`readpassphrase`] or any [`String`] received from [`getpass`] or
 //! [`readpassphrase_into`].
 //!
@@ -71,8 +79,9 @@
 //! # }
 //! ```
 //!
-//! If this crate’s `zeroize` feature is enabled, then its [`Zeroize`] will be replaced by a
-//! re-export of the upstream [`zeroize::Zeroize`].
+//! This crate also ships with a minimal [`Zeroizing`] wrapper that zeroes its contents on drop.
+//! If this crate’s `zeroize` feature is enabled, then its [`Zeroize`] and [`Zeroizing`] will be
+//! replaced by re-exports of the upstream [`zeroize::Zeroize`] and [`zeroize::Zeroizing`].
 //!
 //! # “Mismatched types” errors
 //! The prompt strings in this API are <code>&[CStr]</code>, not <code>&[str]</code>.
@@ -93,7 +102,16 @@
 //! # }
 //! ```
 //!
-//! If you need a dynamic prompt, look at [`CString`](std::ffi::CString).
+//! If you need a dynamic prompt, look at [`Prompt`], which also escapes any terminal control
+//! sequences that might be hiding in text from untrusted sources:
+//! ```no_run
+//! # use readpassphrase_3::{Error, Prompt, getpass};
+//! # fn main() -> Result<(), Error> {
+//! # let comment = "";
+//! let _ = getpass(&Prompt::new(format!("Passphrase for key {comment}: ")))?;
+//! # Ok(())
+//! # }
+//! ```
 //!
 //! # Windows Limitations
 //! The Windows implementation of `readpassphrase(3)` that we are using does not yet support UTF-8
@@ -103,13 +121,66 @@
 //! [0]: https://man.openbsd.org/readpassphrase
 //! [str]: prim@str "str"
 
-use std::{cmp, error, ffi::CStr, fmt, io, mem, str};
+use std::{error, ffi::CStr, fmt, io, mem, mem::MaybeUninit, process, ptr, str};
 
+#[cfg(target_os = "linux")]
+pub use agent::{Agent, AgentCache};
+pub use array::{PassphraseArray, readpassphrase_array, readpassphrase_array_in_place};
+pub use askpass::{AskPass, TtyOrAskPass};
+pub use backend::Backend;
 use bitflags::bitflags;
+pub use buf::{Passphrase, SecretBuf};
+#[cfg(feature = "clap")]
+pub use cli::SecretArg;
+pub use git::GitCredential;
+#[cfg(not(windows))]
+pub use harden::{Hardened, Hardening};
+#[cfg(target_os = "linux")]
+pub use keyring::{Keyring, KeyringCache};
+#[cfg(not(windows))]
+pub use locked::{LockedBuf, getpass_locked};
 #[cfg(any(docsrs, not(feature = "zeroize")))]
-pub use our_zeroize::Zeroize;
+pub use our_zeroize::{Zeroize, Zeroizing};
+#[cfg(all(unix, feature = "pam"))]
+pub use pam::{PamConv, PamConversation, PamMessage, PamResponse};
+use paste::PasteMode;
+pub use pinentry::Pinentry;
+pub use prompt::{Color, Prompt, Sanitize, Style};
+#[cfg(feature = "secrecy")]
+pub use secret::{getpass_secret, readpassphrase_secret, readpassphrase_secret_box};
+pub use source::{Source, Sources};
+#[cfg(target_os = "linux")]
+pub use systemd::SystemdAskPassword;
 #[cfg(all(not(docsrs), feature = "zeroize"))]
-pub use zeroize::Zeroize;
+pub use zeroize::{Zeroize, Zeroizing};
+
+#[cfg(target_os = "linux")]
+mod agent;
+mod array;
+mod askpass;
+mod backend;
+mod buf;
+#[cfg(feature = "clap")]
+mod cli;
+mod control;
+mod git;
+#[cfg(not(windows))]
+mod harden;
+#[cfg(target_os = "linux")]
+mod keyring;
+#[cfg(not(windows))]
+mod locked;
+#[cfg(all(unix, feature = "pam"))]
+mod pam;
+mod paste;
+mod pinentry;
+mod prompt;
+#[cfg(feature = "secrecy")]
+mod secret;
+mod source;
+#[cfg(target_os = "linux")]
+mod systemd;
+mod term;
 
 /// Size of buffer used in [`getpass`].
 ///
@@ -158,6 +229,98 @@ pub enum Error {
     Io(io::Error),
     /// The entered password was not UTF-8.
     Utf8(str::Utf8Error),
+    /// A paste contained more than one line, and [`Paste::SingleLine`] was set.
+    MultilinePaste,
+    /// The entered password contained control characters, and [`Control::Reject`] was set.
+    Control,
+    /// The user cancelled entering the passphrase, e.g. in an [`AskPass`] dialog.
+    Cancelled,
+    /// A password command exited unsuccessfully; see [`Source::command`].
+    Command(process::ExitStatus),
+}
+
+/// Options for reading a passphrase.
+///
+/// These extend the [`Flags`] passed to `readpassphrase(3)` with behavior implemented by this
+/// crate. The free functions in this crate use the [default options][Options::from] for their
+/// `flags`; to change any other option, call the corresponding method here instead:
+/// ```no_run
+/// # use readpassphrase_3::{Error, Flags, Options};
+/// # fn main() -> Result<(), Error> {
+/// let options = Options {
+///     flags: Flags::ECHO_ON,
+///     erase: true,
+///     ..Default::default()
+/// };
+/// let mut buf = vec![0u8; 256];
+/// let pass = options.readpassphrase(c"One-time code: ", &mut buf)?;
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct Options {
+    /// Flags to pass to `readpassphrase(3)`.
+    pub flags: Flags,
+    /// Erase the prompt and any echoed input from the terminal once the passphrase has been read.
+    ///
+    /// This keeps the length of the passphrase, or the passphrase itself with [`Flags::ECHO_ON`],
+    /// out of the terminal’s scrollback. It is done with cursor control sequences, and so only if
+    /// the prompt was written to a capable terminal; otherwise, this option has no effect.
+    pub erase: bool,
+    /// How to handle pasted input.
+    pub paste: Paste,
+    /// How to handle control characters in the input.
+    pub control: Control,
+}
+
+/// How to handle pasted input.
+///
+/// Password managers commonly paste passphrases, sometimes along with a trailing newline. With
+/// [bracketed paste][0] enabled, the terminal marks the start and end of each paste, so that this
+/// crate can treat it as one atomic insertion and report that it happened via [`Outcome::pasted`].
+///
+/// Bracketed paste is only enabled if the prompt is written to a capable terminal; otherwise,
+/// pasted input is handled as though this were [`Paste::Off`].
+///
+/// [0]: https://invisible-island.net/xterm/xterm-paste64.html
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum Paste {
+    /// Do not enable bracketed paste; pasted input is read the same as typed input.
+    #[default]
+    Off,
+    /// Enable bracketed paste.
+    ///
+    /// A newline at the end of a paste ends the input, as though it had been typed. Any newlines
+    /// before the end of a paste are kept in the passphrase.
+    Bracketed,
+    /// Enable bracketed paste, and fail with [`Error::MultilinePaste`] if a paste contains more
+    /// than one line.
+    SingleLine,
+}
+
+/// How to handle control characters in the input.
+///
+/// This applies to the C0 and C1 control characters, including any escape sequences they start,
+/// such as ANSI sequences sent by the terminal or by a pasted string. It does not apply to the
+/// terminating newline, which is never part of the passphrase.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum Control {
+    /// Keep control characters in the passphrase.
+    #[default]
+    Allow,
+    /// Remove control characters and escape sequences from the passphrase.
+    Strip,
+    /// Fail with [`Error::Control`] if the passphrase contains any control characters.
+    Reject,
+}
+
+/// Information about how a passphrase was read, returned by [`Options::read`] and
+/// [`Options::read_into`].
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct Outcome {
+    /// Whether any of the passphrase was pasted. This is only detected if bracketed paste was
+    /// enabled; see [`Paste`].
+    pub pasted: bool,
 }
 
 /// Reads a passphrase using `readpassphrase(3)`.
@@ -188,22 +351,35 @@ pub fn readpassphrase<'a>(
     buf: &'a mut [u8],
     flags: Flags,
 ) -> Result<&'a str, Error> {
-    #[cfg(debug_assertions)]
-    {
-        // Fill `buf` with nonzero bytes to check that `ffi::readpassphrase` wrote a NUL.
-        buf.fill(1);
-    }
-    let prompt = prompt.as_ptr();
-    let buf_ptr = buf.as_mut_ptr().cast();
-    let bufsiz = buf.len();
-    let flags = flags.bits();
-    // SAFETY: `prompt` is a NUL-terminated byte sequence, and `buf_ptr` is an allocation of at
-    // least `bufsiz` bytes, by construction from `&CStr` and `&mut [u8]` respectively.
-    let res = unsafe { ffi::readpassphrase(prompt, buf_ptr, bufsiz, flags) };
-    if res.is_null() {
-        return Err(io::Error::last_os_error().into());
-    }
-    Ok(CStr::from_bytes_until_nul(buf).unwrap().to_str()?)
+    Options::from(flags).readpassphrase(prompt, buf)
+}
+
+/// Reads a passphrase using `readpassphrase(3)` into a possibly uninitialized buffer.
+///
+/// This is like [`readpassphrase`], except that `buf` need not be initialized beforehand; only
+/// the returned passphrase and its NUL terminator are written to. This avoids the cost of
+/// initializing a large or frequently reused buffer, e.g. the spare capacity of a [`Vec`]:
+/// ```no_run
+/// # use readpassphrase_3::{Error, Flags, readpassphrase_uninit};
+/// # fn main() -> Result<(), Error> {
+/// let mut buf = Vec::with_capacity(1 << 16);
+/// let pass = readpassphrase_uninit(c"Key: ", buf.spare_capacity_mut(), Flags::empty())?;
+/// # Ok(())
+/// # }
+/// ```
+///
+/// # Errors
+/// As with [`readpassphrase`].
+///
+/// # Security
+/// As with [`readpassphrase`], `buf` might contain sensitive data after this returns, and should
+/// be zeroed as soon as possible.
+pub fn readpassphrase_uninit<'a>(
+    prompt: &CStr,
+    buf: &'a mut [MaybeUninit<u8>],
+    flags: Flags,
+) -> Result<&'a str, Error> {
+    Options::from(flags).readpassphrase_uninit(prompt, buf)
 }
 
 /// Reads a passphrase using `readpassphrase(3)`, returning a [`String`].
@@ -233,22 +409,74 @@ pub fn getpass(prompt: &CStr) -> Result<String, Error> {
     Ok(readpassphrase_into(prompt, buf, Flags::empty())?)
 }
 
+/// Reads a passphrase using `readpassphrase(3)`, lending it to `f`.
+///
+/// The passphrase is read into a buffer of [`PASSWORD_LEN`] bytes that this function owns, and
+/// that it zeroes before returning, whether `f` returns normally or panics. This means that there
+/// is nothing for the caller to zero, as long as `f` does not copy the passphrase elsewhere:
+/// ```no_run
+/// # use readpassphrase_3::{Error, Flags, with_passphrase};
+/// # fn main() -> Result<(), Error> {
+/// # fn check(_: &str) -> bool { true }
+/// let ok = with_passphrase(c"Password: ", Flags::REQUIRE_TTY, |pass| check(pass))?;
+/// # Ok(())
+/// # }
+/// ```
+///
+/// # Errors
+/// Returns [`Err`] if `readpassphrase(3)` itself failed or if the entered password is not UTF-8,
+/// in which case `f` is not called. See [`try_with_passphrase`] if `f` itself can fail.
+pub fn with_passphrase<R>(
+    prompt: &CStr,
+    flags: Flags,
+    f: impl FnOnce(&str) -> R,
+) -> Result<R, Error> {
+    Options::from(flags).with_passphrase(prompt, f)
+}
+
+/// Reads a passphrase using `readpassphrase(3)`, lending it to the fallible `f`.
+///
+/// This is like [`with_passphrase`], but for closures returning a [`Result`] whose error type can
+/// represent this crate’s [`Error`]:
+/// ```no_run
+/// # use std::{error, io};
+/// # use readpassphrase_3::{Flags, try_with_passphrase};
+/// # struct Vault;
+/// # impl Vault { fn open(_: &str) -> io::Result<Vault> { Ok(Vault) } }
+/// fn unlock() -> Result<Vault, Box<dyn error::Error>> {
+///     try_with_passphrase(c"Passphrase: ", Flags::empty(), |pass| Ok(Vault::open(pass)?))
+/// }
+/// ```
+///
+/// # Errors
+/// Returns [`Err`] if reading the passphrase failed as with [`with_passphrase`], or if `f` failed.
+pub fn try_with_passphrase<R, E: From<Error>>(
+    prompt: &CStr,
+    flags: Flags,
+    f: impl FnOnce(&str) -> Result<R, E>,
+) -> Result<R, E> {
+    Options::from(flags).try_with_passphrase(prompt, f)
+}
+
 /// An [`Error`] from [`readpassphrase_into`] containing the passed buffer.
 ///
-/// The buffer is accessible via [`IntoError::into_bytes`][0], and the `Error` via
-/// [`IntoError::error`].
+/// The buffer is accessible via [`IntoError::into_inner`][0] (or [`IntoError::into_bytes`] for a
+/// [`Vec`]), and the `Error` via [`IntoError::error`].
 ///
-/// If [`into_bytes`][0] is not called, the buffer is automatically zeroed on drop.
+/// If the buffer is not taken, it is automatically [wiped][SecretBuf::wipe] on drop.
 ///
-/// [0]: IntoError::into_bytes
+/// [0]: IntoError::into_inner
 #[derive(Debug)]
-pub struct IntoError(Error, Option<Vec<u8>>);
+pub struct IntoError<B: SecretBuf = Vec<u8>>(Error, Option<B>);
 
 /// Reads a passphrase using `readpassphrase(3)`, returning `buf` as a [`String`].
 ///
 /// The returned [`String`] reuses `buf`’s memory; no copies are made, and `buf` is never
 /// reallocated.
 ///
+/// Other types of storage may be read into in the same way, returning a passphrase of a type
+/// that depends on the storage; see [`SecretBuf`].
+///
 /// `buf`’s full allocation will be  used, whether initialized or not, up to [4KiB][MAX_CAPACITY];
 /// i.e., the following two statements are equivalent:
 /// ```no_run
@@ -288,38 +516,175 @@ pub struct IntoError(Error, Option<Vec<u8>>);
 /// # Ok(())
 /// # }
 /// ```
-pub fn readpassphrase_into(
+pub fn readpassphrase_into<B: SecretBuf>(
     prompt: &CStr,
-    mut buf: Vec<u8>,
+    buf: B,
     flags: Flags,
-) -> Result<String, IntoError> {
-    let bufsiz = cmp::max(buf.len(), cmp::min(buf.capacity(), MAX_CAPACITY));
-    if cfg!(debug_assertions) {
-        // Fill `buf` with nonzero bytes to check that `ffi::readpassphrase` wrote a NUL.
-        buf.fill(1);
-        buf.resize(bufsiz, 1);
-    } else {
-        buf.resize(bufsiz, 0);
-    }
-    let prompt = prompt.as_ptr();
-    let buf_ptr = buf.as_mut_ptr().cast();
-    let flags = flags.bits();
-    // SAFETY: By construction as with `readpassphrase` above.
-    let res = unsafe { ffi::readpassphrase(prompt, buf_ptr, bufsiz, flags) };
-    if res.is_null() {
-        buf.clear();
-        return Err(IntoError(io::Error::last_os_error().into(), Some(buf)));
-    }
-    let len = buf.iter().position(|&b| b == 0).unwrap();
-    buf.truncate(len);
-    String::from_utf8(buf).map_err(|e| {
-        let err = e.utf8_error();
-        let buf = e.into_bytes();
-        IntoError(Error::Utf8(err), Some(buf))
-    })
+) -> Result<B::Passphrase, IntoError<B>> {
+    Options::from(flags).readpassphrase_into(prompt, buf)
 }
 
-impl IntoError {
+impl Options {
+    /// Reads a passphrase as with [`readpassphrase`], using these options.
+    ///
+    /// # Errors
+    /// As with [`readpassphrase`]. Additionally, fails with [`Error::MultilinePaste`] or
+    /// [`Error::Control`] if the input was rejected per [`Options::paste`] or
+    /// [`Options::control`]; `buf` is zeroed in these cases.
+    pub fn readpassphrase<'a>(&self, prompt: &CStr, buf: &'a mut [u8]) -> Result<&'a str, Error> {
+        Ok(self.read(prompt, buf)?.0)
+    }
+
+    /// Reads a passphrase as with [`Options::readpassphrase`], also returning an [`Outcome`].
+    ///
+    /// # Errors
+    /// As with [`Options::readpassphrase`].
+    pub fn read<'a>(&self, prompt: &CStr, buf: &'a mut [u8]) -> Result<(&'a str, Outcome), Error> {
+        // SAFETY: `read_uninit` only writes initialized bytes to `buf`.
+        let buf = unsafe { &mut *(ptr::from_mut(buf) as *mut [MaybeUninit<u8>]) };
+        self.read_uninit(prompt, buf)
+    }
+
+    /// Reads a passphrase as with [`readpassphrase_uninit`], using these options.
+    ///
+    /// # Errors
+    /// As with [`Options::readpassphrase`].
+    pub fn readpassphrase_uninit<'a>(
+        &self,
+        prompt: &CStr,
+        buf: &'a mut [MaybeUninit<u8>],
+    ) -> Result<&'a str, Error> {
+        Ok(self.read_uninit(prompt, buf)?.0)
+    }
+
+    /// Reads a passphrase as with [`Options::readpassphrase_uninit`], also returning an
+    /// [`Outcome`].
+    ///
+    /// # Errors
+    /// As with [`Options::readpassphrase`].
+    pub fn read_uninit<'a>(
+        &self,
+        prompt: &CStr,
+        buf: &'a mut [MaybeUninit<u8>],
+    ) -> Result<(&'a str, Outcome), Error> {
+        let (buf, outcome) = self.read_raw(prompt, buf)?;
+        Ok((str::from_utf8(buf)?, outcome))
+    }
+
+    /// Reads a passphrase as with [`readpassphrase_into`], using these options.
+    ///
+    /// # Errors
+    /// As with [`readpassphrase_into`]. Additionally, fails with [`Error::MultilinePaste`] or
+    /// [`Error::Control`] if the input was rejected per [`Options::paste`] or
+    /// [`Options::control`]; the returned buffer is zeroed in these cases.
+    pub fn readpassphrase_into<B: SecretBuf>(
+        &self,
+        prompt: &CStr,
+        buf: B,
+    ) -> Result<B::Passphrase, IntoError<B>> {
+        Ok(self.read_into(prompt, buf)?.0)
+    }
+
+    /// Reads a passphrase as with [`Options::readpassphrase_into`], also returning an
+    /// [`Outcome`].
+    ///
+    /// # Errors
+    /// As with [`Options::readpassphrase_into`].
+    pub fn read_into<B: SecretBuf>(
+        &self,
+        prompt: &CStr,
+        buf: B,
+    ) -> Result<(B::Passphrase, Outcome), IntoError<B>> {
+        backend::read_into(buf, |buf| self.read_raw(prompt, buf))
+    }
+
+    /// Reads a passphrase as with [`with_passphrase`], using these options.
+    ///
+    /// # Errors
+    /// As with [`with_passphrase`] and [`Options::readpassphrase`].
+    pub fn with_passphrase<R>(&self, prompt: &CStr, f: impl FnOnce(&str) -> R) -> Result<R, Error> {
+        let mut buf = vec![0u8; PASSWORD_LEN];
+        let buf = ZeroOnDrop(&mut buf);
+        let pass = self.readpassphrase(prompt, buf.0)?;
+        Ok(f(pass))
+    }
+
+    /// Reads a passphrase as with [`try_with_passphrase`], using these options.
+    ///
+    /// # Errors
+    /// As with [`try_with_passphrase`] and [`Options::readpassphrase`].
+    pub fn try_with_passphrase<R, E: From<Error>>(
+        &self,
+        prompt: &CStr,
+        f: impl FnOnce(&str) -> Result<R, E>,
+    ) -> Result<R, E> {
+        self.with_passphrase(prompt, f)?
+    }
+
+    /// Calls `readpassphrase(3)`, returning the initialized passphrase written to `buf`.
+    fn read_raw<'a>(
+        &self,
+        prompt: &CStr,
+        buf: &'a mut [MaybeUninit<u8>],
+    ) -> Result<(&'a mut [u8], Outcome), Error> {
+        if cfg!(debug_assertions) {
+            // Fill `buf` with nonzero bytes to check that `ffi::readpassphrase` wrote a NUL.
+            buf.fill(MaybeUninit::new(1));
+        }
+        let paste_mode = match self.paste {
+            Paste::Off => None,
+            _ => PasteMode::enter(self.flags),
+        };
+        let mut flags = self.flags;
+        if paste_mode.is_some() {
+            // `PasteMode` has turned echo off; see `paste.rs` for why.
+            flags |= Flags::ECHO_ON;
+        }
+        let prompt_ptr = prompt.as_ptr();
+        let buf_ptr = buf.as_mut_ptr().cast();
+        let bufsiz = buf.len();
+        // SAFETY: `prompt_ptr` is a NUL-terminated byte sequence, and `buf_ptr` is an allocation
+        // of at least `bufsiz` bytes, by construction from `&CStr` and `&mut [_]` respectively.
+        let res = unsafe { ffi::readpassphrase(prompt_ptr, buf_ptr, bufsiz, flags.bits()) };
+        if res.is_null() {
+            return Err(io::Error::last_os_error().into());
+        }
+        // SAFETY: `ffi::readpassphrase` initialized `buf` up to and including a NUL. (In debug
+        // builds, all of `buf` is initialized, so we panic here if it did not write a NUL.)
+        let mut len = buf
+            .iter()
+            .position(|b| unsafe { b.assume_init() } == 0)
+            .unwrap();
+        let mut init_len = len + 1;
+        if paste_mode.is_some() {
+            // Appending the rest of a paste may use the rest of `buf`.
+            buf[init_len..].fill(MaybeUninit::new(0));
+            init_len = buf.len();
+        }
+        // SAFETY: `buf[..init_len]` is initialized per above.
+        let init = unsafe { &mut *(ptr::from_mut(&mut buf[..init_len]) as *mut [u8]) };
+        if self.erase {
+            term::erase_prompt(prompt, &init[..len], self.flags);
+        }
+        let mut outcome = Outcome::default();
+        if let Some(paste_mode) = paste_mode {
+            (len, outcome.pasted) = paste_mode.unbracket(init, len, self.paste)?;
+        }
+        len = control::filter(&mut init[..=len], len, self.control)?;
+        Ok((&mut init[..len], outcome))
+    }
+}
+
+impl From<Flags> for Options {
+    fn from(flags: Flags) -> Self {
+        Options {
+            flags,
+            ..Default::default()
+        }
+    }
+}
+
+impl<B: SecretBuf> IntoError<B> {
     /// Return the [`Error`] corresponding to this.
     pub fn error(&self) -> &Error {
         &self.0
@@ -328,6 +693,18 @@ impl IntoError {
     /// Returns the buffer that was passed to [`readpassphrase_into`].
     ///
     /// # Security
+    /// The returned buffer may contain sensitive data. It is the caller’s responsibility to zero it
+    /// as soon as possible if needed, e.g. using [`SecretBuf::wipe`].
+    pub fn into_inner(mut self) -> B {
+        self.1.take().unwrap()
+    }
+}
+
+impl IntoError {
+    /// Returns the buffer that was passed to [`readpassphrase_into`]. Unless the error is
+    /// [`Error::Utf8`], the buffer is empty.
+    ///
+    /// # Security
     /// The returned buffer may contain sensitive data in its spare capacity, even if the
     /// buffer’s length is zero. It is the caller’s responsibility to zero it as soon as possible
     /// if needed, e.g. using [`Zeroize`]:
@@ -339,37 +716,52 @@ impl IntoError {
     /// // ...
     /// buf.zeroize();
     /// ```
-    pub fn into_bytes(mut self) -> Vec<u8> {
-        self.1.take().unwrap()
+    pub fn into_bytes(self) -> Vec<u8> {
+        let read = matches!(self.0, Error::Utf8(_));
+        let mut buf = self.into_inner();
+        if !read {
+            // Nothing usable was read, so only the spare capacity is left to zero.
+            buf.clear();
+        }
+        buf
     }
 }
 
-impl error::Error for IntoError {
+impl<B: SecretBuf + fmt::Debug> error::Error for IntoError<B> {
     fn source(&self) -> Option<&(dyn error::Error + 'static)> {
         Some(&self.0)
     }
 }
 
-impl fmt::Display for IntoError {
+impl<B: SecretBuf> fmt::Display for IntoError<B> {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         self.0.fmt(f)
     }
 }
 
-impl Drop for IntoError {
+impl<B: SecretBuf> Drop for IntoError<B> {
     fn drop(&mut self) {
         if let Some(mut buf) = self.1.take() {
-            buf.zeroize();
+            buf.wipe();
         }
     }
 }
 
-impl From<IntoError> for Error {
-    fn from(mut value: IntoError) -> Self {
+impl<B: SecretBuf> From<IntoError<B>> for Error {
+    fn from(mut value: IntoError<B>) -> Self {
         mem::replace(&mut value.0, Error::Io(io::ErrorKind::Other.into()))
     }
 }
 
+/// A buffer that is zeroed when dropped, including during unwinding.
+struct ZeroOnDrop<'a>(&'a mut [u8]);
+
+impl Drop for ZeroOnDrop<'_> {
+    fn drop(&mut self) {
+        self.0.zeroize();
+    }
+}
+
 impl From<io::Error> for Error {
     fn from(value: io::Error) -> Self {
         Error::Io(value)
@@ -384,10 +776,11 @@ impl From<str::Utf8Error> for Error {
 
 impl error::Error for Error {
     fn source(&self) -> Option<&(dyn error::Error + 'static)> {
-        Some(match self {
-            Error::Io(e) => e,
-            Error::Utf8(e) => e,
-        })
+        match self {
+            Error::Io(e) => Some(e),
+            Error::Utf8(e) => Some(e),
+            Error::MultilinePaste | Error::Control | Error::Cancelled | Error::Command(_) => None,
+        }
     }
 }
 
@@ -396,18 +789,22 @@ impl fmt::Display for Error {
         match self {
             Error::Io(e) => e.fmt(f),
             Error::Utf8(e) => e.fmt(f),
+            Error::MultilinePaste => f.write_str("pasted input contained more than one line"),
+            Error::Control => f.write_str("input contained control characters"),
+            Error::Cancelled => f.write_str("passphrase entry was cancelled"),
+            Error::Command(status) => write!(f, "password command failed: {status}"),
         }
     }
 }
 
 #[cfg(any(docsrs, not(feature = "zeroize")))]
 mod our_zeroize {
-    use std::{arch::asm, mem::MaybeUninit};
+    use std::{arch::asm, ffi::CString, fmt, mem, mem::MaybeUninit, ops};
 
     /// A minimal in-crate implementation of a subset of [`zeroize::Zeroize`].
     ///
-    /// This provides compile-fenced memory zeroing for [`String`]s and [`Vec`]s without needing to
-    /// depend on the `zeroize` crate.
+    /// This provides compile-fenced memory zeroing for strings, vectors, slices, arrays, boxes,
+    /// and primitives without needing to depend on the `zeroize` crate.
     ///
     /// If the optional `zeroize` feature is enabled, then the trait is replaced with a re-export of
     /// `zeroize::Zeroize` itself.
@@ -415,15 +812,89 @@ mod our_zeroize {
         fn zeroize(&mut self);
     }
 
-    impl Zeroize for Vec<u8> {
+    /// A minimal in-crate implementation of [`zeroize::Zeroizing`], which zeroes its contents
+    /// when dropped.
+    ///
+    /// If the optional `zeroize` feature is enabled, then this is replaced with a re-export of
+    /// `zeroize::Zeroizing` itself.
+    #[derive(Default, PartialEq, Eq)]
+    #[repr(transparent)]
+    pub struct Zeroizing<Z: Zeroize + ?Sized>(Z);
+
+    mod private {
+        /// Types for which [`Default`] is all zeroes, as with `zeroize::DefaultIsZeroes`.
+        pub trait DefaultIsZeroes: Copy + Default {}
+    }
+    use private::DefaultIsZeroes;
+
+    macro_rules! impl_default_is_zeroes {
+        ($($ty:ty),*) => {
+            $(impl DefaultIsZeroes for $ty {})*
+        };
+    }
+
+    impl_default_is_zeroes!(
+        bool, char, f32, f64, i8, i16, i32, i64, i128, isize, u8, u16, u32, u64, u128, usize
+    );
+
+    impl<Z: DefaultIsZeroes> Zeroize for Z {
+        fn zeroize(&mut self) {
+            *self = Z::default();
+            compile_fence(std::slice::from_ref(self));
+        }
+    }
+
+    impl<Z: DefaultIsZeroes> Zeroize for [Z] {
         fn zeroize(&mut self) {
+            self.fill(Z::default());
+            compile_fence(self);
+        }
+    }
+
+    impl<Z: Zeroize, const N: usize> Zeroize for [Z; N] {
+        fn zeroize(&mut self) {
+            self.iter_mut().for_each(Zeroize::zeroize);
+        }
+    }
+
+    impl<Z: Zeroize> Zeroize for Vec<Z> {
+        fn zeroize(&mut self) {
+            self.iter_mut().for_each(Zeroize::zeroize);
             self.clear();
             let buf = self.spare_capacity_mut();
-            buf.fill(MaybeUninit::zeroed());
+            buf.fill_with(MaybeUninit::zeroed);
             compile_fence(buf);
         }
     }
 
+    impl<Z: Zeroize> Zeroize for Box<[Z]> {
+        fn zeroize(&mut self) {
+            self.iter_mut().for_each(Zeroize::zeroize);
+        }
+    }
+
+    impl<Z: Zeroize> Zeroize for Option<Z> {
+        fn zeroize(&mut self) {
+            if let Some(value) = self {
+                value.zeroize();
+            }
+            *self = None;
+        }
+    }
+
+    impl Zeroize for str {
+        fn zeroize(&mut self) {
+            // SAFETY: all zeroes is valid UTF-8.
+            unsafe { self.as_bytes_mut() }.zeroize();
+        }
+    }
+
+    impl Zeroize for Box<str> {
+        fn zeroize(&mut self) {
+            (**self).zeroize();
+        }
+    }
+
     impl Zeroize for String {
         fn zeroize(&mut self) {
             // SAFETY: we clear the string.
@@ -431,10 +902,76 @@ mod our_zeroize {
         }
     }
 
-    impl Zeroize for [u8] {
+    impl Zeroize for CString {
         fn zeroize(&mut self) {
-            self.fill(0);
-            compile_fence(self);
+            let mut buf = mem::take(self).into_bytes_with_nul();
+            buf.zeroize();
+            // The bytes were wiped above; `CString::new` may shrink or move the allocation, which
+            // then holds only the new empty string.
+            *self = CString::new(buf).unwrap();
+        }
+    }
+
+    impl<Z: Zeroize> Zeroizing<Z> {
+        /// Wraps `value` to be zeroed when dropped.
+        pub fn new(value: Z) -> Self {
+            Zeroizing(value)
+        }
+    }
+
+    impl<Z: Zeroize + ?Sized> Zeroize for Zeroizing<Z> {
+        fn zeroize(&mut self) {
+            self.0.zeroize();
+        }
+    }
+
+    impl<Z: Zeroize + ?Sized> Drop for Zeroizing<Z> {
+        fn drop(&mut self) {
+            self.0.zeroize();
+        }
+    }
+
+    impl<Z: Zeroize + ?Sized> ops::Deref for Zeroizing<Z> {
+        type Target = Z;
+
+        fn deref(&self) -> &Z {
+            &self.0
+        }
+    }
+
+    impl<Z: Zeroize + ?Sized> ops::DerefMut for Zeroizing<Z> {
+        fn deref_mut(&mut self) -> &mut Z {
+            &mut self.0
+        }
+    }
+
+    impl<T: ?Sized, Z: AsRef<T> + Zeroize + ?Sized> AsRef<T> for Zeroizing<Z> {
+        fn as_ref(&self) -> &T {
+            self.0.as_ref()
+        }
+    }
+
+    impl<T: ?Sized, Z: AsMut<T> + Zeroize + ?Sized> AsMut<T> for Zeroizing<Z> {
+        fn as_mut(&mut self) -> &mut T {
+            self.0.as_mut()
+        }
+    }
+
+    impl<Z: Zeroize + Clone> Clone for Zeroizing<Z> {
+        fn clone(&self) -> Self {
+            Zeroizing(self.0.clone())
+        }
+    }
+
+    impl<Z: Zeroize> From<Z> for Zeroizing<Z> {
+        fn from(value: Z) -> Self {
+            Zeroizing(value)
+        }
+    }
+
+    impl<Z: Zeroize + ?Sized> fmt::Debug for Zeroizing<Z> {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            f.debug_struct("Zeroizing").finish_non_exhaustive()
         }
     }
 
@@ -497,6 +1034,17 @@ mod tests {
         };
     }
 
+    #[test]
+    fn test_zero_on_drop() {
+        let mut buf = *b"secret";
+        let res = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            let _buf = ZeroOnDrop(&mut buf);
+            panic!("in closure");
+        }));
+        assert!(res.is_err());
+        assert_eq!([0u8; 6], buf);
+    }
+
     #[test]
     fn test_zeroize() {
         let mut buf = "test".to_string();
@@ -517,5 +1065,21 @@ mod tests {
         slice.zeroize();
         unsafe { buf.set_len(2) };
         assert_eq!(vec![0u8, 1], buf);
+
+        let mut buf = Box::<str>::from("test");
+        buf.zeroize();
+        assert_eq!("\0\0\0\0", &*buf);
+        let mut buf = *b"test";
+        buf.zeroize();
+        assert_eq!([0; 4], buf);
+        let mut buf = std::ffi::CString::from(c"test");
+        buf.zeroize();
+        assert_eq!(c"", &*buf);
+        let mut buf = Some("test".to_string());
+        buf.zeroize();
+        assert_eq!(None, buf);
+        let mut buf = Zeroizing::new(vec!['t'; 4]);
+        buf.zeroize();
+        assert!(buf.is_empty());
     }
 }
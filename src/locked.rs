@@ -0,0 +1,326 @@
+//! Locked, guard-paged memory for passphrases.
+
+use std::{
+    ffi::CStr,
+    fmt, io,
+    mem::MaybeUninit,
+    ops::{Deref, DerefMut},
+    ptr::{self, NonNull},
+    slice, str,
+};
+
+use crate::{Error, Options, PASSWORD_LEN, Passphrase, SecretBuf, Zeroize};
+
+/// A buffer in memory that is locked into RAM and kept out of core dumps.
+///
+/// The buffer is allocated in its own pages, with an inaccessible guard page on either side to
+/// catch overruns. Its pages are locked with `mlock(2)` so that they are never swapped out, and
+/// marked to be excluded from core dumps where supported. On Linux, the memory is allocated with
+/// `memfd_secret(2)` if it is available, which also removes it from the kernel’s direct map.
+///
+/// The buffer is zeroed, unlocked, and unmapped when dropped.
+///
+/// A `LockedBuf` may be read into as a [`SecretBuf`]; [`getpass_locked`] does this with a buffer of
+/// [`PASSWORD_LEN`] bytes.
+///
+/// # Limits
+/// Locked memory is limited by `RLIMIT_MEMLOCK`, which may be as low as 64KiB. Each buffer locks
+/// at least one page regardless of its length.
+pub struct LockedBuf {
+    ptr: NonNull<u8>,
+    len: usize,
+}
+
+// SAFETY: `LockedBuf` uniquely owns its memory, like a `Box<[u8]>`.
+unsafe impl Send for LockedBuf {}
+// SAFETY: as above.
+unsafe impl Sync for LockedBuf {}
+
+/// Reads a passphrase using `readpassphrase(3)` into a [`LockedBuf`].
+///
+/// This is like [`getpass`][crate::getpass], but the passphrase is never stored in ordinary heap
+/// memory:
+/// ```no_run
+/// # use readpassphrase_3::{Error, getpass_locked};
+/// # fn main() -> Result<(), Error> {
+/// let pass = getpass_locked(c"Password: ")?;
+/// let pass: &str = &pass;
+/// # Ok(())
+/// # }
+/// ```
+///
+/// # Errors
+/// Returns [`Error::Io`] if the buffer could not be allocated, and otherwise fails as with
+/// [`getpass`][crate::getpass].
+pub fn getpass_locked(prompt: &CStr) -> Result<Passphrase<LockedBuf>, Error> {
+    Options::default().readpassphrase_locked(prompt)
+}
+
+impl Options {
+    /// Reads a passphrase as with [`getpass_locked`], using these options.
+    ///
+    /// # Errors
+    /// As with [`getpass_locked`] and [`Options::readpassphrase`].
+    pub fn readpassphrase_locked(&self, prompt: &CStr) -> Result<Passphrase<LockedBuf>, Error> {
+        let buf = LockedBuf::new(PASSWORD_LEN)?;
+        Ok(self.readpassphrase_into(prompt, buf)?)
+    }
+}
+
+impl LockedBuf {
+    /// Allocates a zeroed, locked buffer of `len` bytes.
+    ///
+    /// # Errors
+    /// Returns [`Err`] if the memory could not be mapped or locked.
+    pub fn new(len: usize) -> io::Result<LockedBuf> {
+        let page = page_size();
+        let data_len = len.max(1).div_ceil(page) * page;
+        let map_len = data_len + 2 * page;
+        // SAFETY: this creates a new mapping, and has no other effect on memory.
+        let base = unsafe {
+            libc::mmap(
+                ptr::null_mut(),
+                map_len,
+                libc::PROT_NONE,
+                libc::MAP_PRIVATE | libc::MAP_ANON,
+                -1,
+                0,
+            )
+        };
+        if base == libc::MAP_FAILED {
+            return Err(io::Error::last_os_error());
+        }
+        // SAFETY: `base` is a mapping of `map_len` bytes, which is at least two pages.
+        let data = unsafe { base.cast::<u8>().add(page) };
+        // SAFETY: `data` is `data_len` bytes within our mapping, between the guard pages.
+        if let Err(e) = unsafe { map_data(data, data_len) } {
+            // SAFETY: `base` is our mapping, which nothing else refers to.
+            unsafe { libc::munmap(base, map_len) };
+            return Err(e);
+        }
+        Ok(LockedBuf {
+            // SAFETY: `data` is within a successful mapping, and so is not null.
+            ptr: unsafe { NonNull::new_unchecked(data) },
+            len,
+        })
+    }
+
+    /// Returns the length of the buffer in bytes.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Returns whether the buffer is empty.
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    fn data_len(&self) -> usize {
+        let page = page_size();
+        self.len.max(1).div_ceil(page) * page
+    }
+}
+
+/// Makes the `data_len` bytes at `data` accessible, locked, and excluded from core dumps.
+///
+/// # Safety
+/// `data` must be page-aligned, and `data_len` bytes of it must be reserved for this buffer.
+unsafe fn map_data(data: *mut u8, data_len: usize) -> io::Result<()> {
+    // SAFETY: as guaranteed by the caller.
+    if unsafe { map_secret(data, data_len) } {
+        // Secret memory is always locked and excluded from core dumps.
+        return Ok(());
+    }
+    // SAFETY: as above.
+    let res = unsafe { libc::mprotect(data.cast(), data_len, libc::PROT_READ | libc::PROT_WRITE) };
+    if res != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    // SAFETY: as above.
+    if unsafe { exclude_from_core(data, data_len) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    // SAFETY: as above.
+    if unsafe { libc::mlock(data.cast(), data_len) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// Maps `data_len` bytes at `data` with `memfd_secret(2)`, returning whether this succeeded.
+///
+/// # Safety
+/// As with [`map_data`].
+#[cfg(all(
+    target_os = "linux",
+    any(
+        target_arch = "x86",
+        target_arch = "x86_64",
+        target_arch = "aarch64",
+        target_arch = "riscv64"
+    )
+))]
+unsafe fn map_secret(data: *mut u8, data_len: usize) -> bool {
+    // SAFETY: `memfd_secret` takes a flags argument and has no effect on memory.
+    let fd = unsafe { libc::syscall(libc::SYS_memfd_secret, 0) };
+    let Ok(fd) = libc::c_int::try_from(fd) else {
+        return false;
+    };
+    if fd < 0 {
+        return false;
+    }
+    let Ok(size) = libc::off_t::try_from(data_len) else {
+        // SAFETY: `fd` is ours.
+        unsafe { libc::close(fd) };
+        return false;
+    };
+    // SAFETY: `fd` is ours, and `data` is reserved for us per the caller.
+    let res = unsafe {
+        if libc::ftruncate(fd, size) == 0 {
+            libc::mmap(
+                data.cast(),
+                data_len,
+                libc::PROT_READ | libc::PROT_WRITE,
+                libc::MAP_SHARED | libc::MAP_FIXED,
+                fd,
+                0,
+            )
+        } else {
+            libc::MAP_FAILED
+        }
+    };
+    // SAFETY: `fd` is ours; the mapping keeps the memory alive.
+    unsafe { libc::close(fd) };
+    res != libc::MAP_FAILED
+}
+
+#[cfg(not(all(
+    target_os = "linux",
+    any(
+        target_arch = "x86",
+        target_arch = "x86_64",
+        target_arch = "aarch64",
+        target_arch = "riscv64"
+    )
+)))]
+unsafe fn map_secret(_data: *mut u8, _data_len: usize) -> bool {
+    false
+}
+
+/// # Safety
+/// As with [`map_data`].
+#[cfg(any(target_os = "linux", target_os = "android"))]
+unsafe fn exclude_from_core(data: *mut u8, data_len: usize) -> libc::c_int {
+    // SAFETY: as guaranteed by the caller.
+    unsafe { libc::madvise(data.cast(), data_len, libc::MADV_DONTDUMP) }
+}
+
+/// # Safety
+/// As with [`map_data`].
+#[cfg(any(target_os = "freebsd", target_os = "dragonfly"))]
+unsafe fn exclude_from_core(data: *mut u8, data_len: usize) -> libc::c_int {
+    // SAFETY: as guaranteed by the caller.
+    unsafe { libc::madvise(data.cast(), data_len, libc::MADV_NOCORE) }
+}
+
+#[cfg(not(any(
+    target_os = "linux",
+    target_os = "android",
+    target_os = "freebsd",
+    target_os = "dragonfly"
+)))]
+unsafe fn exclude_from_core(_data: *mut u8, _data_len: usize) -> libc::c_int {
+    0
+}
+
+fn page_size() -> usize {
+    // SAFETY: `sysconf` has no effect on memory.
+    let page = unsafe { libc::sysconf(libc::_SC_PAGESIZE) };
+    usize::try_from(page).unwrap_or(4096)
+}
+
+impl Deref for LockedBuf {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        // SAFETY: `ptr` points to at least `len` readable bytes, initialized to zero by `mmap`.
+        unsafe { slice::from_raw_parts(self.ptr.as_ptr(), self.len) }
+    }
+}
+
+impl DerefMut for LockedBuf {
+    fn deref_mut(&mut self) -> &mut [u8] {
+        // SAFETY: as above, and the bytes are writable.
+        unsafe { slice::from_raw_parts_mut(self.ptr.as_ptr(), self.len) }
+    }
+}
+
+impl fmt::Debug for LockedBuf {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("LockedBuf")
+            .field("len", &self.len)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Drop for LockedBuf {
+    fn drop(&mut self) {
+        let page = page_size();
+        let data_len = self.data_len();
+        let data = self.ptr.as_ptr();
+        // SAFETY: `data` and `data_len` describe our accessible data pages, which are between
+        // two guard pages in one mapping that nothing else refers to.
+        unsafe {
+            slice::from_raw_parts_mut(data, data_len).zeroize();
+            libc::munlock(data.cast(), data_len);
+            libc::munmap(data.sub(page).cast(), data_len + 2 * page);
+        }
+    }
+}
+
+// SAFETY: `as_uninit` and `as_uninit_mut` both return the buffer’s memory.
+unsafe impl SecretBuf for LockedBuf {
+    type Passphrase = Passphrase<Self>;
+
+    fn as_uninit(&self) -> &[MaybeUninit<u8>] {
+        // SAFETY: `MaybeUninit<u8>` has the same layout as `u8`, and the result cannot be written
+        // to.
+        unsafe { &*(ptr::from_ref(&**self) as *const [MaybeUninit<u8>]) }
+    }
+
+    unsafe fn as_uninit_mut(&mut self) -> &mut [MaybeUninit<u8>] {
+        // SAFETY: `MaybeUninit<u8>` has the same layout as `u8`, and the caller only writes
+        // initialized bytes.
+        unsafe { &mut *(ptr::from_mut(&mut **self) as *mut [MaybeUninit<u8>]) }
+    }
+
+    unsafe fn into_passphrase(
+        self,
+        len: usize,
+    ) -> Result<Passphrase<Self>, (Self, str::Utf8Error)> {
+        // SAFETY: as guaranteed by the caller.
+        unsafe { Passphrase::from_buf(self, len) }
+    }
+
+    fn wipe(&mut self) {
+        self[..].zeroize();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_locked_buf() {
+        let mut buf = LockedBuf::new(100).unwrap();
+        assert_eq!(100, buf.len());
+        assert_eq!(0, buf.as_ptr() as usize % page_size());
+        assert!(buf.iter().all(|&b| b == 0));
+        buf[..4].copy_from_slice(b"pass");
+        let pass = unsafe { buf.into_passphrase(4) }.unwrap();
+        assert_eq!("pass", &*pass);
+        assert_eq!(b"pass", &pass.into_inner()[..4]);
+        assert!(LockedBuf::new(0).unwrap().is_empty());
+    }
+}
@@ -0,0 +1,265 @@
+//! A PAM conversation function.
+
+use std::{
+    ffi::{CStr, c_char, c_int, c_void},
+    panic::{self, AssertUnwindSafe},
+    ptr, slice,
+};
+
+use crate::{Flags, Options, PASSWORD_LEN, Prompt, Sanitize, Zeroize};
+
+/// `PAM_SUCCESS`.
+const SUCCESS: c_int = 0;
+/// `PAM_BUF_ERR`, a memory allocation failed.
+const BUF_ERR: c_int = 5;
+/// `PAM_CONV_ERR`, the conversation failed.
+const CONV_ERR: c_int = 19;
+/// `PAM_MAX_NUM_MSG`, the most messages that may be passed to the conversation at once.
+const MAX_NUM_MSG: c_int = 32;
+
+/// `PAM_PROMPT_ECHO_OFF`.
+const PROMPT_ECHO_OFF: c_int = 1;
+/// `PAM_PROMPT_ECHO_ON`.
+const PROMPT_ECHO_ON: c_int = 2;
+/// `PAM_ERROR_MSG`.
+const ERROR_MSG: c_int = 3;
+/// `PAM_TEXT_INFO`.
+const TEXT_INFO: c_int = 4;
+
+/// A PAM conversation that prompts using `readpassphrase(3)`.
+///
+/// Pass the [`PamConv`] returned by [`PamConversation::pam_conv`] to `pam_start(3)` from your
+/// PAM bindings of choice. Prompts are then handled as follows:
+/// - `PAM_PROMPT_ECHO_OFF`, e.g. a password, is read with [`options`][Self::options].
+/// - `PAM_PROMPT_ECHO_ON`, e.g. a username, is read with [`Flags::ECHO_ON`] added.
+/// - `PAM_ERROR_MSG` is written to stderr, and `PAM_TEXT_INFO` to stdout.
+///
+/// Prompts are sanitized as by [`Prompt::new`], and messages as by [`Sanitize::Multiline`].
+/// Responses are zeroed once copied to PAM, which frees them; Linux-PAM and OpenPAM overwrite them
+/// before doing so.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub struct PamConversation {
+    /// Options for reading responses.
+    pub options: Options,
+}
+
+/// A `struct pam_conv`, as passed to `pam_start(3)`.
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub struct PamConv {
+    /// The conversation function.
+    pub conv: Option<
+        unsafe extern "C" fn(
+            num_msg: c_int,
+            msg: *mut *const PamMessage,
+            resp: *mut *mut PamResponse,
+            appdata_ptr: *mut c_void,
+        ) -> c_int,
+    >,
+    /// The data passed to the conversation function.
+    pub appdata_ptr: *mut c_void,
+}
+
+/// A `struct pam_message`, as passed to a conversation function.
+#[repr(C)]
+#[derive(Debug)]
+pub struct PamMessage {
+    /// The kind of message, e.g. `PAM_PROMPT_ECHO_OFF`.
+    pub msg_style: c_int,
+    /// The text of the message.
+    pub msg: *const c_char,
+}
+
+/// A `struct pam_response`, as returned by a conversation function.
+#[repr(C)]
+#[derive(Debug)]
+pub struct PamResponse {
+    /// The response, allocated with `malloc(3)`.
+    pub resp: *mut c_char,
+    /// Unused; always zero.
+    pub resp_retcode: c_int,
+}
+
+impl PamConversation {
+    /// Returns a conversation reading responses with `options`.
+    pub fn new(options: Options) -> Self {
+        PamConversation { options }
+    }
+
+    /// Returns a `struct pam_conv` for this conversation.
+    ///
+    /// The result refers to `self`, which must therefore outlive the PAM handle it is passed to.
+    pub fn pam_conv(&self) -> PamConv {
+        PamConv {
+            conv: Some(converse),
+            appdata_ptr: ptr::from_ref(self).cast_mut().cast(),
+        }
+    }
+
+    /// Responds to `msg`, returning the response allocated with `malloc(3)`, if any.
+    fn respond(&self, msg: &PamMessage) -> Result<*mut c_char, c_int> {
+        if msg.msg.is_null() {
+            return Err(CONV_ERR);
+        }
+        // SAFETY: PAM passes NUL-terminated messages.
+        let text = unsafe { CStr::from_ptr(msg.msg) }.to_string_lossy();
+        let flags = match msg.msg_style {
+            PROMPT_ECHO_OFF => self.options.flags,
+            PROMPT_ECHO_ON => self.options.flags | Flags::ECHO_ON,
+            // Messages, e.g. from `pam_motd` or of password expiry, may span several lines.
+            ERROR_MSG => {
+                let msg = Prompt::with_sanitize(&text, Sanitize::Multiline);
+                eprintln!("{}", msg.to_string_lossy());
+                return Ok(ptr::null_mut());
+            }
+            TEXT_INFO => {
+                let msg = Prompt::with_sanitize(&text, Sanitize::Multiline);
+                println!("{}", msg.to_string_lossy());
+                return Ok(ptr::null_mut());
+            }
+            _ => return Err(CONV_ERR),
+        };
+        let prompt = Prompt::new(&text);
+        let options = Options {
+            flags,
+            ..self.options
+        };
+        let mut buf = [0u8; PASSWORD_LEN];
+        let res = match options.readpassphrase(&prompt, &mut buf) {
+            Ok(pass) => copy_to_malloc(pass.as_bytes()),
+            Err(_) => Err(CONV_ERR),
+        };
+        buf.zeroize();
+        res
+    }
+}
+
+/// The conversation function, which responds to each message in turn using the
+/// [`PamConversation`] given by `appdata_ptr`.
+///
+/// # Safety
+/// The arguments must be as passed by PAM, with `appdata_ptr` from [`PamConversation::pam_conv`].
+unsafe extern "C" fn converse(
+    num_msg: c_int,
+    msg: *mut *const PamMessage,
+    resp: *mut *mut PamResponse,
+    appdata_ptr: *mut c_void,
+) -> c_int {
+    if !(1..=MAX_NUM_MSG).contains(&num_msg) || msg.is_null() || resp.is_null() {
+        return CONV_ERR;
+    }
+    // SAFETY: `appdata_ptr` is from `PamConversation::pam_conv`, and so is null or valid.
+    let Some(conversation) = (unsafe { appdata_ptr.cast::<PamConversation>().as_ref() }) else {
+        return CONV_ERR;
+    };
+    let num_msg = num_msg as usize;
+    // SAFETY: PAM frees the responses with `free(3)`.
+    let replies = unsafe { libc::calloc(num_msg, size_of::<PamResponse>()) }.cast::<PamResponse>();
+    if replies.is_null() {
+        return BUF_ERR;
+    }
+    // SAFETY: `calloc` returned zeroed memory for `num_msg` responses, and PAM passes `num_msg`
+    // messages.
+    let (replies_slice, msgs) = unsafe {
+        (
+            slice::from_raw_parts_mut(replies, num_msg),
+            slice::from_raw_parts(msg, num_msg),
+        )
+    };
+    // A panic must not unwind into PAM.
+    let res = panic::catch_unwind(AssertUnwindSafe(|| {
+        for (reply, &msg) in replies_slice.iter_mut().zip(msgs) {
+            // SAFETY: PAM passes valid messages.
+            let msg = unsafe { msg.as_ref() }.ok_or(CONV_ERR)?;
+            reply.resp = conversation.respond(msg)?;
+        }
+        Ok(())
+    }))
+    .unwrap_or(Err(CONV_ERR));
+    match res {
+        Ok(()) => {
+            // SAFETY: PAM passes a valid `resp`.
+            unsafe { *resp = replies };
+            SUCCESS
+        }
+        Err(e) => {
+            for reply in replies_slice {
+                // SAFETY: each response is either null or a string from `copy_to_malloc`.
+                unsafe { free_response(reply.resp) };
+            }
+            // SAFETY: `replies` is from `calloc`, and is no longer used.
+            unsafe { libc::free(replies.cast()) };
+            e
+        }
+    }
+}
+
+/// Copies `bytes` into a NUL-terminated string allocated with `malloc(3)`.
+fn copy_to_malloc(bytes: &[u8]) -> Result<*mut c_char, c_int> {
+    // SAFETY: the result is checked for null before use.
+    let out = unsafe { libc::malloc(bytes.len() + 1) }.cast::<u8>();
+    if out.is_null() {
+        return Err(BUF_ERR);
+    }
+    // SAFETY: `out` is an allocation of `bytes.len() + 1` bytes.
+    unsafe {
+        ptr::copy_nonoverlapping(bytes.as_ptr(), out, bytes.len());
+        *out.add(bytes.len()) = 0;
+    }
+    Ok(out.cast())
+}
+
+/// Zeroes and frees a response string.
+///
+/// # Safety
+/// `resp` must be null or a string allocated with `malloc(3)`.
+unsafe fn free_response(resp: *mut c_char) {
+    if resp.is_null() {
+        return;
+    }
+    // SAFETY: per this function’s safety requirements.
+    unsafe {
+        let len = CStr::from_ptr(resp).count_bytes();
+        slice::from_raw_parts_mut(resp.cast::<u8>(), len).zeroize();
+        libc::free(resp.cast());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_converse() {
+        let conversation = PamConversation::default();
+        let conv = conversation.pam_conv();
+        let info = PamMessage {
+            msg_style: TEXT_INFO,
+            msg: c"info".as_ptr(),
+        };
+        let error = PamMessage {
+            msg_style: ERROR_MSG,
+            msg: c"error".as_ptr(),
+        };
+        let binary = PamMessage {
+            msg_style: 7,
+            msg: c"".as_ptr(),
+        };
+        let mut msgs = [ptr::from_ref(&info), ptr::from_ref(&error)];
+        let mut resp = ptr::null_mut();
+        let converse = conv.conv.unwrap();
+        // SAFETY: the arguments are valid, as PAM would pass them.
+        unsafe {
+            let res = converse(2, msgs.as_mut_ptr(), &mut resp, conv.appdata_ptr);
+            assert_eq!(SUCCESS, res);
+            assert!((*resp).resp.is_null() && (*resp.add(1)).resp.is_null());
+            libc::free(resp.cast());
+
+            msgs[1] = &binary;
+            let mut resp = ptr::null_mut();
+            let res = converse(2, msgs.as_mut_ptr(), &mut resp, conv.appdata_ptr);
+            assert_eq!(CONV_ERR, res);
+            assert!(resp.is_null());
+        }
+    }
+}
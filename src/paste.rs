@@ -0,0 +1,331 @@
+//! Bracketed paste handling.
+//!
+//! When `readpassphrase(3)` turns echo off, it restores the terminal with `TCSAFLUSH` once it has
+//! read a line, discarding any input still pending. That would include the rest of a paste that
+//! contained a newline, which we need to see to tell whether the paste had more than one line. So
+//! while bracketed paste is enabled, we turn echo off ourselves and pass [`Flags::ECHO_ON`] to
+//! `readpassphrase(3)`, which then leaves the terminal alone. Like `readpassphrase(3)`, we restore
+//! the terminal before any signal that might stop or terminate the process is delivered.
+
+use crate::{Error, Flags, Paste, Zeroize, term::Capability};
+
+const START: &[u8] = b"\x1b[200~";
+const END: &[u8] = b"\x1b[201~";
+const ENABLE: &[u8] = b"\x1b[?2004h";
+const DISABLE: &[u8] = b"\x1b[?2004l";
+
+/// Bracketed paste mode, enabled on the terminal for the lifetime of this value.
+pub(crate) struct PasteMode(imp::PasteMode);
+
+impl PasteMode {
+    /// Enables bracketed paste mode and turns echo off per `flags`, if the prompt goes to a
+    /// capable terminal.
+    pub(crate) fn enter(flags: Flags) -> Option<PasteMode> {
+        if Capability::of_prompt(flags) == Capability::None {
+            return None;
+        }
+        imp::PasteMode::enter(flags).map(PasteMode)
+    }
+
+    /// Removes bracketed paste markers from the NUL-terminated passphrase of `len` bytes in
+    /// `buf`, returning its new length and whether any of it was pasted.
+    ///
+    /// If the input ended partway through a paste, then the rest of the paste is read from the
+    /// terminal and appended to the passphrase per `paste`.
+    pub(crate) fn unbracket(
+        &self,
+        buf: &mut [u8],
+        len: usize,
+        paste: Paste,
+    ) -> Result<(usize, bool), Error> {
+        let (new_len, pasted, open) = strip_markers(&mut buf[..len]);
+        buf[new_len..len].zeroize();
+        if !open {
+            return Ok((new_len, pasted));
+        }
+        let mut rest = self.0.read_until(END, buf.len() + END.len());
+        let res = append_rest(buf, new_len, &rest, paste);
+        rest.zeroize();
+        Ok((res?, pasted))
+    }
+}
+
+/// Removes paste markers from `buf` in place, returning the length of the remaining input, whether
+/// any markers were found, and whether the input ended inside a paste.
+fn strip_markers(buf: &mut [u8]) -> (usize, bool, bool) {
+    let (mut r, mut w) = (0, 0);
+    let (mut pasted, mut open) = (false, false);
+    while r < buf.len() {
+        if buf[r..].starts_with(START) {
+            (pasted, open) = (true, true);
+            r += START.len();
+        } else if buf[r..].starts_with(END) {
+            open = false;
+            r += END.len();
+        } else {
+            buf[w] = buf[r];
+            w += 1;
+            r += 1;
+        }
+    }
+    (w, pasted, open)
+}
+
+/// Appends the `rest` of a paste that contained a newline to the passphrase of `len` bytes in
+/// `buf`, returning the new length of the passphrase.
+fn append_rest(buf: &mut [u8], len: usize, rest: &[u8], paste: Paste) -> Result<usize, Error> {
+    let rest = rest.strip_suffix(END).unwrap_or(rest);
+    let trimmed = rest.trim_ascii_end();
+    if trimmed.is_empty() {
+        buf[len] = 0;
+        return Ok(len);
+    }
+    if paste == Paste::SingleLine {
+        buf.zeroize();
+        return Err(Error::MultilinePaste);
+    }
+    let mut len = len;
+    for &b in [b'\n'].iter().chain(trimmed) {
+        if len + 1 >= buf.len() {
+            break;
+        }
+        buf[len] = if b == b'\r' { b'\n' } else { b };
+        len += 1;
+    }
+    buf[len] = 0;
+    Ok(len)
+}
+
+#[cfg(not(windows))]
+mod imp {
+    use std::{
+        cell::UnsafeCell,
+        ffi::c_int,
+        fs::{File, OpenOptions},
+        io::{Read, Write},
+        mem::MaybeUninit,
+        os::fd::AsRawFd,
+        ptr,
+        sync::{Mutex, MutexGuard, PoisonError},
+    };
+
+    use super::{DISABLE, ENABLE};
+    use crate::{Flags, Zeroize};
+
+    /// Signals that `readpassphrase(3)` handles.
+    const SIGNALS: [c_int; 9] = [
+        libc::SIGALRM,
+        libc::SIGHUP,
+        libc::SIGINT,
+        libc::SIGPIPE,
+        libc::SIGQUIT,
+        libc::SIGTERM,
+        libc::SIGTSTP,
+        libc::SIGTTIN,
+        libc::SIGTTOU,
+    ];
+
+    struct State {
+        fd: c_int,
+        orig: libc::termios,
+        term: libc::termios,
+        prev: [libc::sigaction; SIGNALS.len()],
+    }
+
+    /// State shared with the signal handler.
+    ///
+    /// This is written only while holding `LOCK` and before installing the signal handler, and
+    /// read only by the signal handler.
+    struct Shared(UnsafeCell<MaybeUninit<State>>);
+
+    // SAFETY: See above.
+    unsafe impl Sync for Shared {}
+
+    static LOCK: Mutex<()> = Mutex::new(());
+    static STATE: Shared = Shared(UnsafeCell::new(MaybeUninit::uninit()));
+
+    pub(super) struct PasteMode {
+        tty: File,
+        _lock: MutexGuard<'static, ()>,
+    }
+
+    impl PasteMode {
+        pub(super) fn enter(flags: Flags) -> Option<PasteMode> {
+            let lock = LOCK.lock().unwrap_or_else(PoisonError::into_inner);
+            let mut tty = OpenOptions::new()
+                .read(true)
+                .write(true)
+                .open("/dev/tty")
+                .ok()?;
+            let fd = tty.as_raw_fd();
+            let mut orig = MaybeUninit::uninit();
+            // SAFETY: `tcgetattr` initializes `orig` if it succeeds.
+            if unsafe { libc::tcgetattr(fd, orig.as_mut_ptr()) } != 0 {
+                return None;
+            }
+            // SAFETY: `tcgetattr` succeeded.
+            let orig = unsafe { orig.assume_init() };
+            let mut term = orig;
+            if !flags.contains(Flags::ECHO_ON) {
+                term.c_lflag &= !(libc::ECHO | libc::ECHONL);
+            }
+            // SAFETY: We hold `LOCK`, and our handler is not yet installed.
+            let state = unsafe { &mut *STATE.0.get() }.write(State {
+                fd,
+                orig,
+                term,
+                // SAFETY: `sigaction` is plain old data; these are overwritten below.
+                prev: unsafe { MaybeUninit::zeroed().assume_init() },
+            });
+            // SAFETY: `sigaction` is plain old data.
+            let mut action: libc::sigaction = unsafe { MaybeUninit::zeroed().assume_init() };
+            action.sa_sigaction = handle_signal as extern "C" fn(c_int) as libc::sighandler_t;
+            // Let the handler deliver the signal to the previous action while it runs.
+            action.sa_flags = libc::SA_NODEFER;
+            for (sig, prev) in SIGNALS.iter().zip(state.prev.iter_mut()) {
+                // SAFETY: `action` and `prev` are valid `sigaction`s.
+                unsafe { libc::sigaction(*sig, &action, prev) };
+            }
+            // SAFETY: `term` is a valid `termios`.
+            unsafe { libc::tcsetattr(fd, libc::TCSAFLUSH, &state.term) };
+            _ = tty.write_all(ENABLE);
+            Some(PasteMode { tty, _lock: lock })
+        }
+
+        /// Reads pending input up to and including `end`, or until no more arrives.
+        pub(super) fn read_until(&self, end: &[u8], limit: usize) -> Vec<u8> {
+            let fd = self.tty.as_raw_fd();
+            // SAFETY: We hold `LOCK`, so `STATE` is initialized.
+            let state = unsafe { (*STATE.0.get()).assume_init_ref() };
+            let mut raw = state.term;
+            raw.c_lflag &= !libc::ICANON;
+            raw.c_cc[libc::VMIN] = 1;
+            raw.c_cc[libc::VTIME] = 0;
+            // SAFETY: `raw` is a valid `termios`.
+            unsafe { libc::tcsetattr(fd, libc::TCSANOW, &raw) };
+            let mut ret = Vec::new();
+            let mut b = [0u8];
+            let mut pfd = libc::pollfd {
+                fd,
+                events: libc::POLLIN,
+                revents: 0,
+            };
+            while ret.len() < limit && !ret.ends_with(end) {
+                // Wait up to 100ms for more input.
+                // SAFETY: `pfd` is a valid `pollfd`.
+                if unsafe { libc::poll(&mut pfd, 1, 100) } != 1 {
+                    break;
+                }
+                match (&self.tty).read(&mut b) {
+                    Ok(1) => ret.push(b[0]),
+                    _ => break,
+                }
+            }
+            b[..].zeroize();
+            // SAFETY: `state.term` is a valid `termios`.
+            unsafe { libc::tcsetattr(fd, libc::TCSANOW, &state.term) };
+            ret
+        }
+    }
+
+    impl Drop for PasteMode {
+        fn drop(&mut self) {
+            // SAFETY: We hold `LOCK`, so `STATE` is initialized.
+            let state = unsafe { (*STATE.0.get()).assume_init_ref() };
+            _ = self.tty.write_all(DISABLE);
+            // As `readpassphrase(3)` does, discard any pending input if we changed the terminal.
+            let when = if state.term.c_lflag != state.orig.c_lflag {
+                libc::TCSAFLUSH
+            } else {
+                libc::TCSANOW
+            };
+            // SAFETY: `state.orig` is a valid `termios`.
+            unsafe { libc::tcsetattr(state.fd, when, &state.orig) };
+            for (sig, prev) in SIGNALS.iter().zip(state.prev.iter()) {
+                // SAFETY: `prev` is the valid `sigaction` we saved in `enter`.
+                unsafe { libc::sigaction(*sig, prev, ptr::null_mut()) };
+            }
+        }
+    }
+
+    /// Restores the terminal and delivers `sig` to its previous action, then, if the process
+    /// continues, sets the terminal back up.
+    extern "C" fn handle_signal(sig: c_int) {
+        // SAFETY: This handler is only installed while `STATE` is initialized.
+        let state = unsafe { (*STATE.0.get()).assume_init_ref() };
+        let Some(i) = SIGNALS.iter().position(|&s| s == sig) else {
+            return;
+        };
+        // SAFETY: These calls are all async-signal-safe, and are passed valid arguments.
+        unsafe {
+            libc::write(state.fd, DISABLE.as_ptr().cast(), DISABLE.len());
+            libc::tcsetattr(state.fd, libc::TCSAFLUSH, &state.orig);
+            let mut ours = MaybeUninit::uninit();
+            libc::sigaction(sig, &state.prev[i], ours.as_mut_ptr());
+            libc::raise(sig);
+            libc::sigaction(sig, ours.as_ptr(), ptr::null_mut());
+            libc::tcsetattr(state.fd, libc::TCSAFLUSH, &state.term);
+            libc::write(state.fd, ENABLE.as_ptr().cast(), ENABLE.len());
+        }
+    }
+}
+
+#[cfg(windows)]
+mod imp {
+    use std::convert::Infallible;
+
+    use crate::Flags;
+
+    pub(super) struct PasteMode(Infallible);
+
+    impl PasteMode {
+        pub(super) fn enter(_flags: Flags) -> Option<PasteMode> {
+            None
+        }
+
+        pub(super) fn read_until(&self, _end: &[u8], _limit: usize) -> Vec<u8> {
+            match self.0 {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_strip_markers() {
+        let mut buf = *b"\x1b[200~abc\x1b[201~d";
+        let (len, pasted, open) = strip_markers(&mut buf);
+        assert_eq!(
+            (b"abcd".as_slice(), true, false),
+            (&buf[..len], pasted, open)
+        );
+        let mut buf = *b"ab\x1b[200~cd";
+        let (len, pasted, open) = strip_markers(&mut buf);
+        assert_eq!(
+            (b"abcd".as_slice(), true, true),
+            (&buf[..len], pasted, open)
+        );
+        let mut buf = *b"abcd";
+        let (len, pasted, open) = strip_markers(&mut buf);
+        assert_eq!(
+            (b"abcd".as_slice(), false, false),
+            (&buf[..len], pasted, open)
+        );
+    }
+
+    #[test]
+    fn test_append_rest() {
+        let mut buf = *b"abc\0\0\0\0\0";
+        let len = append_rest(&mut buf, 3, b"\x1b[201~", Paste::SingleLine).unwrap();
+        assert_eq!(b"abc\0", &buf[..len + 1]);
+        let len = append_rest(&mut buf, 3, b"\n\x1b[201~", Paste::SingleLine).unwrap();
+        assert_eq!(b"abc\0", &buf[..len + 1]);
+        let len = append_rest(&mut buf, 3, b"de\rfgh\n\x1b[201~", Paste::Bracketed).unwrap();
+        assert_eq!(b"abc\nde\n\0", &buf[..len + 1]);
+        let err = append_rest(&mut buf, 3, b"de\x1b[201~", Paste::SingleLine).unwrap_err();
+        assert!(matches!(err, Error::MultilinePaste));
+        assert_eq!([0u8; 8], buf);
+    }
+}
@@ -0,0 +1,255 @@
+//! Reading passphrases from `pinentry` programs.
+
+use std::{
+    ffi::CStr,
+    io::{self, Read, Write},
+    mem::MaybeUninit,
+    path::PathBuf,
+    process::{ChildStdin, ChildStdout, Command, Stdio},
+};
+
+use crate::{Backend, Error, Zeroize, backend};
+
+/// The maximum length of an Assuan line, not including the newline.
+const LINE_MAX: usize = 1000;
+
+/// The `libgpg-error` codes for a cancelled operation.
+const GPG_ERR_CANCELED: u32 = 99;
+const GPG_ERR_FULLY_CANCELED: u32 = 198;
+
+/// A [`Backend`] that runs a [`pinentry`][0] program, as used by `gpg-agent`.
+///
+/// The program is spoken to using the Assuan protocol. The prompt is shown next to the text
+/// entry, and the optional [`description`][Pinentry::description] and
+/// [`error`][Pinentry::error] are shown above it:
+/// ```no_run
+/// # use readpassphrase_3::{Backend, Error, Pinentry};
+/// # fn main() -> Result<(), Error> {
+/// let mut pinentry = Pinentry::new("pinentry");
+/// pinentry.description = Some("Enter the passphrase for your signing key".into());
+/// let pass = pinentry.getpass(c"Passphrase:")?;
+/// # Ok(())
+/// # }
+/// ```
+///
+/// If the user cancels the dialog, [`Error::Cancelled`] is returned.
+///
+/// [0]: https://www.gnupg.org/related_software/pinentry/
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Pinentry {
+    program: PathBuf,
+    /// Text explaining what the passphrase is for.
+    pub description: Option<String>,
+    /// An error to show, e.g. that a previously entered passphrase was wrong.
+    pub error: Option<String>,
+}
+
+impl Pinentry {
+    /// Returns a backend that runs `program`.
+    pub fn new(program: impl Into<PathBuf>) -> Self {
+        Pinentry {
+            program: program.into(),
+            description: None,
+            error: None,
+        }
+    }
+
+    fn run(&self, prompt: &CStr, buf: &mut [u8]) -> Result<usize, Error> {
+        let cap = buf
+            .len()
+            .checked_sub(1)
+            .ok_or(io::Error::from(io::ErrorKind::InvalidInput))?;
+        let mut child = Command::new(&self.program)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()?;
+        let mut conn = Connection {
+            stdin: child.stdin.take().unwrap(),
+            stdout: child.stdout.take().unwrap(),
+            line: [0; LINE_MAX],
+        };
+        let res = self.getpin(&mut conn, prompt, &mut buf[..cap]);
+        // Closing the connection makes pinentry exit, if it did not on `BYE`.
+        drop(conn);
+        child.wait()?;
+        res
+    }
+
+    fn getpin(&self, conn: &mut Connection, prompt: &CStr, buf: &mut [u8]) -> Result<usize, Error> {
+        conn.response(|_| Ok(()))?;
+        conn.command(b"SETPROMPT", prompt.to_bytes())?;
+        if let Some(desc) = &self.description {
+            conn.command(b"SETDESC", desc.as_bytes())?;
+        }
+        if let Some(error) = &self.error {
+            conn.command(b"SETERROR", error.as_bytes())?;
+        }
+        conn.send(b"GETPIN", b"")?;
+        let mut len = 0;
+        conn.response(|data| decode(data, buf, &mut len))?;
+        // Failing to say goodbye is harmless, as the passphrase has been read.
+        _ = conn.send(b"BYE", b"");
+        Ok(len)
+    }
+}
+
+impl Backend for Pinentry {
+    fn read_bytes<'a>(
+        &self,
+        prompt: &CStr,
+        buf: &'a mut [MaybeUninit<u8>],
+    ) -> Result<&'a mut [u8], Error> {
+        backend::read_zeroed(buf, |buf| self.run(prompt, buf))
+    }
+}
+
+/// An Assuan connection to a pinentry program.
+struct Connection {
+    stdin: ChildStdin,
+    stdout: ChildStdout,
+    /// The last line read, which may hold part of the passphrase.
+    line: [u8; LINE_MAX],
+}
+
+impl Connection {
+    /// Sends `command` with `arg`, and waits for it to succeed.
+    fn command(&mut self, command: &[u8], arg: &[u8]) -> Result<(), Error> {
+        self.send(command, arg)?;
+        self.response(|_| Ok(()))
+    }
+
+    /// Sends `command` with `arg` escaped.
+    fn send(&mut self, command: &[u8], arg: &[u8]) -> io::Result<()> {
+        let mut line = command.to_vec();
+        if !arg.is_empty() {
+            line.push(b' ');
+            for &b in arg {
+                if matches!(b, b'%' | b'\r' | b'\n') || b.is_ascii_control() {
+                    line.extend_from_slice(format!("%{b:02X}").as_bytes());
+                } else {
+                    line.push(b);
+                }
+            }
+        }
+        if line.len() > LINE_MAX {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "pinentry command is too long",
+            ));
+        }
+        line.push(b'\n');
+        self.stdin.write_all(&line)
+    }
+
+    /// Reads lines up to the final `OK` or `ERR`, passing the data in any `D` lines to `data`.
+    fn response(&mut self, mut data: impl FnMut(&[u8]) -> io::Result<()>) -> Result<(), Error> {
+        loop {
+            let len = self.read_line()?;
+            let line = &self.line[..len];
+            if line == b"OK" || line.starts_with(b"OK ") {
+                return Ok(());
+            } else if let Some(rest) = line.strip_prefix(b"D ") {
+                data(rest)?;
+            } else if let Some(rest) = line.strip_prefix(b"ERR ") {
+                return Err(error(rest));
+            } else if line.starts_with(b"INQUIRE ") {
+                // We have nothing to give, so cancel the inquiry; the command will then fail.
+                self.stdin.write_all(b"CAN\n")?;
+            }
+            // Status (`S`) and comment (`#`) lines are ignored.
+        }
+    }
+
+    /// Reads a line into `self.line` and returns its length.
+    ///
+    /// This reads a byte at a time, so that none of the passphrase is left behind in a buffer.
+    fn read_line(&mut self) -> io::Result<usize> {
+        let mut len = 0;
+        loop {
+            let mut b = 0;
+            match self.stdout.read(std::slice::from_mut(&mut b)) {
+                Ok(0) => return Err(io::ErrorKind::UnexpectedEof.into()),
+                Ok(_) if b == b'\n' => return Ok(len),
+                Ok(_) if len < LINE_MAX => {
+                    self.line[len] = b;
+                    len += 1;
+                }
+                Ok(_) => return Err(invalid_data("pinentry response is too long")),
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(e),
+            }
+        }
+    }
+}
+
+impl Drop for Connection {
+    fn drop(&mut self) {
+        self.line.zeroize();
+    }
+}
+
+/// Percent-decodes `data` into `buf` at `len`, discarding anything past the end of `buf` as
+/// `readpassphrase(3)` does.
+fn decode(data: &[u8], buf: &mut [u8], len: &mut usize) -> io::Result<()> {
+    let mut data = data.iter();
+    while let Some(&b) = data.next() {
+        let b = if b == b'%' {
+            let mut hex = || data.next().and_then(|&d| (d as char).to_digit(16));
+            match (hex(), hex()) {
+                (Some(hi), Some(lo)) => (hi * 16 + lo) as u8,
+                _ => return Err(invalid_data("invalid escape in pinentry data")),
+            }
+        } else {
+            b
+        };
+        if let Some(dst) = buf.get_mut(*len) {
+            *dst = b;
+            *len += 1;
+        }
+    }
+    Ok(())
+}
+
+/// Converts the rest of an `ERR` line, i.e. an error code and description, into an [`Error`].
+fn error(rest: &[u8]) -> Error {
+    let rest = String::from_utf8_lossy(rest);
+    let (code, desc) = rest.split_once(' ').unwrap_or((&rest, ""));
+    match code.parse::<u32>().map(|code| code & 0xffff) {
+        Ok(GPG_ERR_CANCELED | GPG_ERR_FULLY_CANCELED) => Error::Cancelled,
+        _ => io::Error::other(format!("pinentry failed: {desc} ({code})")).into(),
+    }
+}
+
+fn invalid_data(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+
+    const FAKE: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fake-pinentry.sh");
+
+    #[test]
+    fn test_pinentry() {
+        let mut pinentry = Pinentry::new(FAKE);
+        let pass = pinentry.getpass(c"pass%wo\nrd").unwrap();
+        assert_eq!("pass%wo\nrd", pass);
+        let mut buf = [0u8; 5];
+        let pass = pinentry.readpassphrase(c"password", &mut buf).unwrap();
+        assert_eq!("pass", pass);
+
+        pinentry.description = Some("cancel".into());
+        let err = pinentry.getpass(c"").unwrap_err();
+        assert!(matches!(err, Error::Cancelled));
+        pinentry.description = Some("fail".into());
+        let err = pinentry.getpass(c"").unwrap_err();
+        assert!(matches!(err, Error::Io(_)));
+
+        let mut buf = [0u8; 4];
+        assert!(decode(b"a%2", &mut buf, &mut 0).is_err());
+        let mut len = 0;
+        decode(b"%41%0a%25b", &mut buf, &mut len).unwrap();
+        assert_eq!(b"A\n%b", &buf[..len]);
+    }
+}
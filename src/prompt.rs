@@ -0,0 +1,330 @@
+//! Prompts built at runtime, and their styling.
+
+use std::{
+    borrow::Cow,
+    ffi::{CStr, CString},
+    fmt::{self, Write},
+    ops::Deref,
+};
+
+use crate::{Flags, term::Capability};
+
+/// How a [`Prompt`] treats terminal control characters in its text.
+///
+/// The characters affected are the C0 controls (including newline and tab), DEL, the C1 controls,
+/// and the Unicode bidirectional formatting characters. Any of these may be used to rewrite what
+/// the terminal displays, e.g. to hide that echo is on or to reorder the prompt text.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum Sanitize {
+    /// Replace control characters with their Rust escape sequences, e.g. `\u{1b}`.
+    #[default]
+    Escape,
+    /// Like [`Sanitize::Escape`], but keep newlines, for text that is meant to span several lines,
+    /// such as a notice or a confirmation with details above the question.
+    Multiline,
+    /// Remove control characters.
+    Strip,
+    /// Pass the text through unchanged.
+    ///
+    /// NUL characters cannot appear in a C string and are always escaped.
+    Off,
+}
+
+/// A prompt built at runtime, e.g. from a host name or a key comment.
+///
+/// Text from untrusted sources may contain escape sequences that would be interpreted by the
+/// terminal when `readpassphrase(3)` prints the prompt. A `Prompt` escapes these by default; see
+/// [`Sanitize`] for the characters affected and for other modes.
+///
+/// `Prompt` dereferences to [`CStr`], so it may be passed anywhere a prompt is expected:
+/// ```no_run
+/// # use readpassphrase_3::{Error, Prompt, getpass};
+/// # fn main() -> Result<(), Error> {
+/// # let host = "example.com";
+/// let prompt = Prompt::new(format!("Password for {host}: "));
+/// let pass = getpass(&prompt)?;
+/// # Ok(())
+/// # }
+/// ```
+///
+/// # Styling
+/// A prompt may be given a [`Style`] and a dimmed hint line shown above it. These are only
+/// applied by [`Prompt::render`], and only if the prompt will be written to a terminal that
+/// supports them:
+/// ```no_run
+/// # use readpassphrase_3::{Color, Error, Flags, Prompt, Style, readpassphrase_into};
+/// # fn main() -> Result<(), Error> {
+/// let flags = Flags::REQUIRE_TTY;
+/// let prompt = Prompt::new("Password: ")
+///     .style(Style::new().bold().color(Color::Cyan))
+///     .hint("(input hidden)");
+/// let pass = readpassphrase_into(&prompt.render(flags), Vec::with_capacity(256), flags)?;
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Prompt {
+    text: String,
+    hint: Option<String>,
+    sanitize: Sanitize,
+    style: Style,
+    plain: CString,
+}
+
+/// Text styling for a [`Prompt`].
+///
+/// Styles are emitted as ANSI escape sequences, and are omitted entirely unless the prompt is being
+/// written to a terminal. Colors are additionally omitted if the [`NO_COLOR`][0] environment
+/// variable is set to a non-empty value, and no styling is done if `TERM` is `dumb`.
+///
+/// [0]: https://no-color.org
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub struct Style {
+    bold: bool,
+    dim: bool,
+    color: Option<Color>,
+}
+
+/// A foreground color for a [`Style`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Color {
+    Black,
+    Red,
+    Green,
+    Yellow,
+    Blue,
+    Magenta,
+    Cyan,
+    White,
+}
+
+impl Prompt {
+    /// Creates a prompt from `text`, escaping any control characters.
+    pub fn new(text: impl AsRef<str>) -> Self {
+        Self::with_sanitize(text, Sanitize::default())
+    }
+
+    /// Creates a prompt from `text`, treating control characters according to `sanitize`.
+    pub fn with_sanitize(text: impl AsRef<str>, sanitize: Sanitize) -> Self {
+        let text = sanitize_str(text.as_ref(), sanitize);
+        let mut prompt = Prompt {
+            text,
+            hint: None,
+            sanitize,
+            style: Style::new(),
+            plain: CString::default(),
+        };
+        prompt.update_plain();
+        prompt
+    }
+
+    /// Sets the style of the prompt text.
+    pub fn style(mut self, style: Style) -> Self {
+        self.style = style;
+        self
+    }
+
+    /// Sets a hint line to show dimmed above the prompt, e.g. `(input hidden)`.
+    ///
+    /// The hint is sanitized in the same way as the prompt text.
+    pub fn hint(mut self, hint: impl AsRef<str>) -> Self {
+        self.hint = Some(sanitize_str(hint.as_ref(), self.sanitize));
+        self.update_plain();
+        self
+    }
+
+    /// Returns the prompt as a [`CStr`], without any styling.
+    pub fn as_c_str(&self) -> &CStr {
+        &self.plain
+    }
+
+    /// Returns the prompt to pass to `readpassphrase(3)` with `flags`.
+    ///
+    /// The returned prompt is styled only if `readpassphrase(3)` would write it to a capable
+    /// terminal; in particular, it is never styled if [`Flags::STDIN`] is passed or if `/dev/tty`
+    /// is unavailable, since then the prompt is written to stderr.
+    pub fn render(&self, flags: Flags) -> Cow<'_, CStr> {
+        self.render_for(Capability::of_prompt(flags))
+    }
+
+    pub(crate) fn render_for(&self, capability: Capability) -> Cow<'_, CStr> {
+        let style = match capability {
+            Capability::None => return Cow::Borrowed(&self.plain),
+            Capability::NoColor => Style {
+                color: None,
+                ..self.style
+            },
+            Capability::Full => self.style,
+        };
+        if style == Style::new() && self.hint.is_none() {
+            return Cow::Borrowed(&self.plain);
+        }
+        let mut ret = String::new();
+        if let Some(hint) = &self.hint {
+            writeln!(ret, "{}{hint}{}", Style::new().dim(), Reset).unwrap();
+        }
+        if style == Style::new() {
+            ret.push_str(&self.text);
+        } else {
+            write!(ret, "{style}{}{}", self.text, Reset).unwrap();
+        }
+        Cow::Owned(CString::new(ret).unwrap())
+    }
+
+    fn update_plain(&mut self) {
+        let plain = match &self.hint {
+            Some(hint) => format!("{hint}\n{}", self.text),
+            None => self.text.clone(),
+        };
+        // Every NUL has been replaced by `sanitize_str`.
+        self.plain = CString::new(plain).unwrap();
+    }
+}
+
+impl Style {
+    /// Returns an empty style.
+    pub const fn new() -> Self {
+        Style {
+            bold: false,
+            dim: false,
+            color: None,
+        }
+    }
+
+    /// Makes text bold.
+    pub const fn bold(mut self) -> Self {
+        self.bold = true;
+        self
+    }
+
+    /// Makes text dim.
+    pub const fn dim(mut self) -> Self {
+        self.dim = true;
+        self
+    }
+
+    /// Sets the foreground color of text.
+    pub const fn color(mut self, color: Color) -> Self {
+        self.color = Some(color);
+        self
+    }
+}
+
+impl fmt::Display for Style {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.bold {
+            f.write_str("\x1b[1m")?;
+        }
+        if self.dim {
+            f.write_str("\x1b[2m")?;
+        }
+        if let Some(color) = self.color {
+            write!(f, "\x1b[{}m", 30 + color as u8)?;
+        }
+        Ok(())
+    }
+}
+
+struct Reset;
+
+impl fmt::Display for Reset {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("\x1b[0m")
+    }
+}
+
+impl Deref for Prompt {
+    type Target = CStr;
+
+    fn deref(&self) -> &CStr {
+        &self.plain
+    }
+}
+
+impl AsRef<CStr> for Prompt {
+    fn as_ref(&self) -> &CStr {
+        &self.plain
+    }
+}
+
+impl From<&str> for Prompt {
+    fn from(value: &str) -> Self {
+        Prompt::new(value)
+    }
+}
+
+impl From<String> for Prompt {
+    fn from(value: String) -> Self {
+        Prompt::new(value)
+    }
+}
+
+fn sanitize_str(text: &str, sanitize: Sanitize) -> String {
+    let mut ret = String::with_capacity(text.len());
+    for c in text.chars() {
+        match sanitize {
+            Sanitize::Multiline if c == '\n' => ret.push(c),
+            Sanitize::Escape | Sanitize::Multiline if is_control(c) => {
+                write!(ret, "{}", c.escape_debug()).unwrap()
+            }
+            Sanitize::Strip if is_control(c) => {}
+            Sanitize::Off if c == '\0' => ret.push_str("\\0"),
+            _ => ret.push(c),
+        }
+    }
+    ret
+}
+
+fn is_control(c: char) -> bool {
+    matches!(
+        c,
+        '\0'..='\x1f'
+            | '\x7f'..='\u{9f}'
+            | '\u{061c}'
+            | '\u{200e}'
+            | '\u{200f}'
+            | '\u{202a}'..='\u{202e}'
+            | '\u{2066}'..='\u{2069}'
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sanitize() {
+        let text = "a\x1b[8mb\u{9b}c\u{202e}d\ne\0";
+        let prompt = Prompt::new(text);
+        assert_eq!(c"a\\u{1b}[8mb\\u{9b}c\\u{202e}d\\ne\\0", prompt.as_c_str());
+        let prompt = Prompt::with_sanitize(text, Sanitize::Multiline);
+        assert_eq!(c"a\\u{1b}[8mb\\u{9b}c\\u{202e}d\ne\\0", prompt.as_c_str());
+        let prompt = Prompt::with_sanitize(text, Sanitize::Strip);
+        assert_eq!(c"a[8mbcde", prompt.as_c_str());
+        let prompt = Prompt::with_sanitize(text, Sanitize::Off);
+        assert_eq!(c"a\x1b[8mb\u{9b}c\u{202e}d\ne\\0", prompt.as_c_str());
+        assert_eq!(c"Pass for ü: ", &*Prompt::new("Pass for ü: "));
+    }
+
+    #[test]
+    fn test_render() {
+        let prompt = Prompt::new("Pass: ");
+        assert_eq!(c"Pass: ", &*prompt.render_for(Capability::Full));
+        let prompt = prompt
+            .style(Style::new().bold().color(Color::Red))
+            .hint("(hidden\x1b)");
+        assert_eq!(c"(hidden\\u{1b})\nPass: ", prompt.as_c_str());
+        assert_eq!(
+            c"(hidden\\u{1b})\nPass: ",
+            &*prompt.render_for(Capability::None)
+        );
+        assert_eq!(
+            c"\x1b[2m(hidden\\u{1b})\x1b[0m\n\x1b[1mPass: \x1b[0m",
+            &*prompt.render_for(Capability::NoColor)
+        );
+        assert_eq!(
+            c"\x1b[2m(hidden\\u{1b})\x1b[0m\n\x1b[1m\x1b[31mPass: \x1b[0m",
+            &*prompt.render_for(Capability::Full)
+        );
+    }
+}
@@ -0,0 +1,155 @@
+//! Integration with the [`secrecy`] crate.
+
+use std::ffi::CStr;
+
+use secrecy::{ExposeSecret, SecretBox, SecretString};
+
+use crate::{Error, Flags, IntoError, Options, PASSWORD_LEN, Passphrase, Zeroize};
+
+/// Reads a passphrase using `readpassphrase(3)`, returning a [`SecretString`].
+///
+/// This is like [`getpass`][crate::getpass], but the passphrase is only ever held in memory that
+/// is zeroed when dropped:
+/// ```no_run
+/// # use readpassphrase_3::{Error, getpass_secret};
+/// use secrecy::ExposeSecret;
+/// # fn main() -> Result<(), Error> {
+/// let pass = getpass_secret(c"Password: ")?;
+/// let pass: &str = pass.expose_secret();
+/// # Ok(())
+/// # }
+/// ```
+///
+/// # Errors
+/// As with [`getpass`][crate::getpass].
+pub fn getpass_secret(prompt: &CStr) -> Result<SecretString, Error> {
+    let buf = Vec::with_capacity(PASSWORD_LEN);
+    Ok(readpassphrase_secret(prompt, buf, Flags::empty())?)
+}
+
+/// Reads a passphrase using `readpassphrase(3)` into `buf`, returning a [`SecretString`].
+///
+/// This is like [`readpassphrase_into`][crate::readpassphrase_into]. `buf` is used for the
+/// [`SecretString`] directly if the passphrase fills it exactly; otherwise, the passphrase is
+/// copied into an allocation of the right size and `buf` is zeroed.
+///
+/// To read into a [`SecretBox<[u8]>`][SecretBox] instead, use [`readpassphrase_secret_box`].
+///
+/// # Errors
+/// As with [`readpassphrase_into`][crate::readpassphrase_into].
+pub fn readpassphrase_secret(
+    prompt: &CStr,
+    buf: Vec<u8>,
+    flags: Flags,
+) -> Result<SecretString, IntoError> {
+    Options::from(flags).readpassphrase_secret(prompt, buf)
+}
+
+/// Reads a passphrase using `readpassphrase(3)` into `buf`, returning a
+/// [`SecretBox<[u8]>`][SecretBox] holding just the passphrase.
+///
+/// As with [`readpassphrase_secret`], `buf` is returned directly if the passphrase fills it
+/// exactly; otherwise, the passphrase is copied into an allocation of the right size and `buf` is
+/// zeroed. (To keep `buf` itself, pass it to [`readpassphrase_into`][crate::readpassphrase_into]
+/// and use [`Passphrase::into_inner`].)
+/// ```no_run
+/// # use readpassphrase_3::{Error, Flags, PASSWORD_LEN, readpassphrase_secret_box};
+/// use secrecy::{ExposeSecret, SecretBox};
+/// # fn main() -> Result<(), Error> {
+/// let buf = SecretBox::from(vec![0u8; PASSWORD_LEN]);
+/// let pass = readpassphrase_secret_box(c"Password: ", buf, Flags::empty())?;
+/// let pass: &[u8] = pass.expose_secret();
+/// # Ok(())
+/// # }
+/// ```
+///
+/// # Errors
+/// As with [`readpassphrase_into`][crate::readpassphrase_into].
+pub fn readpassphrase_secret_box(
+    prompt: &CStr,
+    buf: SecretBox<[u8]>,
+    flags: Flags,
+) -> Result<SecretBox<[u8]>, IntoError<SecretBox<[u8]>>> {
+    Options::from(flags).readpassphrase_secret_box(prompt, buf)
+}
+
+impl Options {
+    /// Reads a passphrase as with [`readpassphrase_secret`], using these options.
+    ///
+    /// # Errors
+    /// As with [`Options::readpassphrase_into`].
+    pub fn readpassphrase_secret(
+        &self,
+        prompt: &CStr,
+        buf: Vec<u8>,
+    ) -> Result<SecretString, IntoError> {
+        let pass = self.readpassphrase_into(prompt, buf)?;
+        Ok(into_secret_string(pass))
+    }
+
+    /// Reads a passphrase as with [`readpassphrase_secret_box`], using these options.
+    ///
+    /// # Errors
+    /// As with [`Options::readpassphrase_into`].
+    pub fn readpassphrase_secret_box(
+        &self,
+        prompt: &CStr,
+        buf: SecretBox<[u8]>,
+    ) -> Result<SecretBox<[u8]>, IntoError<SecretBox<[u8]>>> {
+        let pass = self.readpassphrase_into(prompt, buf)?;
+        Ok(into_secret_box(pass))
+    }
+}
+
+/// Converts `pass` into a [`SecretString`] without leaving a copy behind.
+///
+/// [`SecretString::from`] shrinks the string’s allocation, which may move it without zeroing the
+/// old one.
+fn into_secret_string(mut pass: String) -> SecretString {
+    if pass.len() == pass.capacity() {
+        return pass.into_boxed_str().into();
+    }
+    let secret = Box::<str>::from(pass.as_str());
+    pass.zeroize();
+    secret.into()
+}
+
+/// Converts `pass` into a [`SecretBox`] of just the passphrase.
+fn into_secret_box(pass: Passphrase<SecretBox<[u8]>>) -> SecretBox<[u8]> {
+    let len = pass.len();
+    let buf = pass.into_inner();
+    if len == buf.expose_secret().len() {
+        return buf;
+    }
+    // `buf` zeroes itself when dropped.
+    SecretBox::new(Box::from(&buf.expose_secret()[..len]))
+}
+
+#[cfg(test)]
+mod tests {
+    use secrecy::ExposeSecret;
+
+    use super::*;
+
+    #[test]
+    fn test_into_secret_string() {
+        let pass = into_secret_string("pass".to_string());
+        assert_eq!("pass", pass.expose_secret());
+        let mut pass = String::with_capacity(16);
+        pass.push_str("pass");
+        assert_eq!("pass", into_secret_string(pass).expose_secret());
+    }
+
+    #[test]
+    fn test_into_secret_box() {
+        let buf = SecretBox::<[u8]>::from(b"pass".to_vec());
+        let pass = unsafe { Passphrase::from_buf(buf, 4) }.unwrap();
+        let ptr = pass.as_ptr();
+        let pass = into_secret_box(pass);
+        assert_eq!(b"pass", pass.expose_secret());
+        assert_eq!(ptr, pass.expose_secret().as_ptr());
+        let buf = SecretBox::<[u8]>::from(b"password".to_vec());
+        let pass = unsafe { Passphrase::from_buf(buf, 4) }.unwrap();
+        assert_eq!(b"pass", into_secret_box(pass).expose_secret());
+    }
+}
@@ -0,0 +1,394 @@
+//! Reading passphrases from a chain of non-interactive sources, falling back to the terminal.
+
+use std::{
+    env,
+    ffi::{CStr, OsString},
+    fmt, fs, io,
+    mem::MaybeUninit,
+    path::PathBuf,
+    process::{Command, Stdio},
+    ptr, str,
+};
+
+use crate::{Backend, Error, Flags, IntoError, Options, PASSWORD_LEN, SecretBuf, Zeroize, backend};
+
+/// A place to read a passphrase from, as part of [`Sources`].
+///
+/// A source that is not present, e.g. an unset environment variable or a missing file, is skipped.
+/// A source that is present but cannot be read fails the whole chain.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Source(Kind);
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+enum Kind {
+    /// The variable’s name, and whether to remove it once read.
+    Env(OsString, bool),
+    File(PathBuf),
+    #[cfg(unix)]
+    Fd(std::os::fd::RawFd),
+    Credential(String),
+    Command(Vec<OsString>, Flags),
+    Tty(Options),
+}
+
+/// A chain of [`Source`]s, which are tried in order until one is present.
+///
+/// This is for programs that may be run either interactively or e.g. in CI:
+/// ```no_run
+/// # use readpassphrase_3::{Error, Flags, Source, Sources};
+/// # fn main() -> Result<(), Error> {
+/// let sources = Sources(vec![
+///     Source::file("/etc/myapp/password"),
+///     Source::credential("myapp-password"),
+///     Source::tty(Flags::REQUIRE_TTY.into()),
+/// ]);
+/// let (pass, source) = sources.getpass(c"Password: ")?;
+/// eprintln!("read password from {source}");
+/// # Ok(())
+/// # }
+/// ```
+///
+/// Sources other than [`Source::tty`] are read up to the first newline, which is not included in
+/// the passphrase. As with `readpassphrase(3)`, anything that does not fit in the buffer is
+/// discarded.
+///
+/// If no source is present, [`Error::Io`] with [`io::ErrorKind::NotFound`] is returned.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub struct Sources(pub Vec<Source>);
+
+impl Source {
+    /// The environment variable `name`.
+    ///
+    /// The variable is left in the environment, where child processes inherit it; use
+    /// [`Source::env_remove`] to remove it once read.
+    pub fn env(name: impl Into<OsString>) -> Self {
+        Source(Kind::Env(name.into(), false))
+    }
+
+    /// The environment variable `name`, which is overwritten in place and removed from the
+    /// environment once read, so that child processes do not inherit it.
+    ///
+    /// # Safety
+    /// Reading a [`Sources`] containing this source, or any clone of it, modifies the environment.
+    /// The caller must ensure that no other thread reads or writes the environment while it is
+    /// read; see [`env::remove_var`].
+    pub unsafe fn env_remove(name: impl Into<OsString>) -> Self {
+        Source(Kind::Env(name.into(), true))
+    }
+
+    /// The file at `path`.
+    pub fn file(path: impl Into<PathBuf>) -> Self {
+        Source(Kind::File(path.into()))
+    }
+
+    /// The inherited file descriptor `fd`, as given by e.g. a `--password-fd` argument. It is
+    /// skipped if it is not open, and is left open after reading.
+    #[cfg(unix)]
+    pub fn fd(fd: std::os::fd::RawFd) -> Self {
+        Source(Kind::Fd(fd))
+    }
+
+    /// The credential `name` in `$CREDENTIALS_DIRECTORY`, as passed to a service by systemd’s
+    /// `LoadCredential=` and related settings.
+    pub fn credential(name: impl Into<String>) -> Self {
+        Source(Kind::Credential(name.into()))
+    }
+
+    /// The first line of output of the command `argv`, as with the `password_command` setting of
+    /// some mail clients. The command is run directly, not by a shell, with no input.
+    ///
+    /// The passphrase is transformed per [`Flags::FORCELOWER`], [`Flags::FORCEUPPER`], and
+    /// [`Flags::SEVENBIT`] in `flags`, as `readpassphrase(3)` would.
+    ///
+    /// This source is always present. If the command exits unsuccessfully, [`Error::Command`] is
+    /// returned.
+    pub fn command(argv: impl IntoIterator<Item = impl Into<OsString>>, flags: Flags) -> Self {
+        Source(Kind::Command(
+            argv.into_iter().map(Into::into).collect(),
+            flags,
+        ))
+    }
+
+    /// The terminal, read with `options`. This source is always present.
+    pub fn tty(options: Options) -> Self {
+        Source(Kind::Tty(options))
+    }
+
+    /// Reads this source into `buf`, returning the length read or [`None`] if it is not present.
+    fn read(&self, prompt: &CStr, buf: &mut [MaybeUninit<u8>]) -> Result<Option<usize>, Error> {
+        if let Kind::Tty(options) = &self.0 {
+            return Ok(Some(options.read_bytes(prompt, buf)?.len()));
+        }
+        let mut present = true;
+        let pass = backend::read_zeroed(buf, |buf| {
+            let len = match &self.0 {
+                Kind::Env(name, false) => read_env(name, buf)?,
+                // SAFETY: per `Source::env_remove`, nothing else is accessing the environment.
+                Kind::Env(name, true) => unsafe { read_env_remove(name, buf)? },
+                Kind::File(path) => read_file(path.clone(), buf)?,
+                #[cfg(unix)]
+                Kind::Fd(fd) => read_fd(*fd, buf)?,
+                Kind::Credential(name) => match env::var_os("CREDENTIALS_DIRECTORY") {
+                    Some(dir) => read_file(PathBuf::from(dir).join(name), buf)?,
+                    None => None,
+                },
+                Kind::Command(argv, flags) => Some(read_command(argv, *flags, buf)?),
+                Kind::Tty(_) => unreachable!(),
+            };
+            present = len.is_some();
+            Ok(len.unwrap_or(0))
+        })?;
+        Ok(present.then_some(pass.len()))
+    }
+}
+
+impl fmt::Display for Source {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.0 {
+            Kind::Env(name, _) => write!(f, "environment variable {}", name.to_string_lossy()),
+            Kind::File(path) => write!(f, "file {}", path.display()),
+            #[cfg(unix)]
+            Kind::Fd(fd) => write!(f, "file descriptor {fd}"),
+            Kind::Credential(name) => write!(f, "credential {name}"),
+            Kind::Command(argv, _) => match argv.first() {
+                Some(program) => write!(f, "command {}", program.to_string_lossy()),
+                None => f.write_str("empty command"),
+            },
+            Kind::Tty(_) => f.write_str("terminal"),
+        }
+    }
+}
+
+impl Sources {
+    /// Reads a passphrase into `buf` from the first present source, returning it along with the
+    /// source it was read from.
+    ///
+    /// # Errors
+    /// As with [`Backend::readpassphrase`].
+    pub fn read<'a>(&self, prompt: &CStr, buf: &'a mut [u8]) -> Result<(&'a str, &Source), Error> {
+        // SAFETY: `read_raw` only writes initialized bytes to `buf`.
+        let buf = unsafe { &mut *(ptr::from_mut(buf) as *mut [MaybeUninit<u8>]) };
+        let (pass, source) = self.read_raw(prompt, buf)?;
+        Ok((str::from_utf8(pass)?, source))
+    }
+
+    /// Reads a passphrase into `buf` as with [`Backend::readpassphrase_into`], also returning the
+    /// source it was read from.
+    ///
+    /// # Errors
+    /// As with [`Backend::readpassphrase_into`].
+    pub fn read_into<B: SecretBuf>(
+        &self,
+        prompt: &CStr,
+        buf: B,
+    ) -> Result<(B::Passphrase, &Source), IntoError<B>> {
+        backend::read_into(buf, |buf| self.read_raw(prompt, buf))
+    }
+
+    /// Reads a passphrase as with [`getpass`][crate::getpass], also returning the source it was
+    /// read from.
+    ///
+    /// # Errors
+    /// As with [`Backend::getpass`].
+    pub fn getpass(&self, prompt: &CStr) -> Result<(String, &Source), Error> {
+        Ok(self.read_into(prompt, Vec::with_capacity(PASSWORD_LEN))?)
+    }
+
+    fn read_raw<'a>(
+        &self,
+        prompt: &CStr,
+        buf: &'a mut [MaybeUninit<u8>],
+    ) -> Result<(&'a mut [u8], &Source), Error> {
+        for source in &self.0 {
+            if let Some(len) = source.read(prompt, buf)? {
+                // SAFETY: `source` initialized the first `len` bytes of `buf`.
+                let pass = unsafe { &mut *(ptr::from_mut(&mut buf[..len]) as *mut [u8]) };
+                return Ok((pass, source));
+            }
+        }
+        Err(io::Error::new(io::ErrorKind::NotFound, "no passphrase source is present").into())
+    }
+}
+
+impl Backend for Sources {
+    fn read_bytes<'a>(
+        &self,
+        prompt: &CStr,
+        buf: &'a mut [MaybeUninit<u8>],
+    ) -> Result<&'a mut [u8], Error> {
+        Ok(self.read_raw(prompt, buf)?.0)
+    }
+}
+
+/// Reads the environment variable `name` into `buf`.
+fn read_env(name: &OsString, buf: &mut [u8]) -> Result<Option<usize>, Error> {
+    let Some(value) = env::var_os(name) else {
+        return Ok(None);
+    };
+    let mut value = value.into_encoded_bytes();
+    let res = backend::read_line(value.as_slice(), buf);
+    value.zeroize();
+    Ok(Some(res?))
+}
+
+/// Reads the environment variable `name` into `buf`, then overwrites and removes it.
+///
+/// # Safety
+/// Nothing else may access the environment at the same time.
+unsafe fn read_env_remove(name: &OsString, buf: &mut [u8]) -> Result<Option<usize>, Error> {
+    let res = read_env(name, buf);
+    // SAFETY: per this function’s safety requirements.
+    unsafe {
+        wipe_env(name);
+        env::remove_var(name);
+    }
+    res
+}
+
+/// Overwrites the value of the environment variable `name` in place, as removing it does not.
+///
+/// # Safety
+/// Nothing else may access the environment at the same time.
+#[cfg(unix)]
+unsafe fn wipe_env(name: &OsString) {
+    use std::{ffi::CString, os::unix::ffi::OsStrExt};
+
+    let Ok(name) = CString::new(name.as_bytes()) else {
+        return;
+    };
+    // SAFETY: per this function’s safety requirements, `getenv`’s result stays valid while we
+    // write to it.
+    unsafe {
+        let value = libc::getenv(name.as_ptr());
+        if !value.is_null() {
+            let len = CStr::from_ptr(value).count_bytes();
+            ptr::write_bytes(value, 0, len);
+        }
+    }
+}
+
+#[cfg(not(unix))]
+unsafe fn wipe_env(_: &OsString) {}
+
+/// Reads the file at `path` into `buf`, returning [`None`] if it does not exist.
+fn read_file(path: PathBuf, buf: &mut [u8]) -> Result<Option<usize>, Error> {
+    let file = match fs::File::open(path) {
+        Ok(file) => file,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e.into()),
+    };
+    Ok(Some(backend::read_line(file, buf)?))
+}
+
+/// Reads `fd` into `buf`, returning [`None`] if it is not open.
+#[cfg(unix)]
+fn read_fd(fd: std::os::fd::RawFd, buf: &mut [u8]) -> Result<Option<usize>, Error> {
+    use std::{fs::File, mem::ManuallyDrop, os::fd::FromRawFd};
+
+    // SAFETY: this only checks whether `fd` is open.
+    if unsafe { libc::fcntl(fd, libc::F_GETFD) } == -1 {
+        return Ok(None);
+    }
+    // SAFETY: `fd` is open, and is not closed when this is dropped.
+    let file = ManuallyDrop::new(unsafe { File::from_raw_fd(fd) });
+    Ok(Some(backend::read_line(&*file, buf)?))
+}
+
+/// Reads the first line of output of `argv` into `buf`, transformed per `flags`.
+fn read_command(argv: &[OsString], flags: Flags, buf: &mut [u8]) -> Result<usize, Error> {
+    let (program, args) = argv
+        .split_first()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "password command is empty"))?;
+    let mut child = Command::new(program)
+        .args(args)
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .spawn()?;
+    let mut stdout = child.stdout.take().unwrap();
+    // The rest of the output, e.g. the metadata after the first line from `pass show`, is read and
+    // discarded, so that the command is not killed by `SIGPIPE`.
+    let res = backend::read_line(&mut stdout, buf).and_then(|len| {
+        backend::drain(stdout)?;
+        Ok(len)
+    });
+    let status = child.wait();
+    let len = res?;
+    let status = status?;
+    if !status.success() {
+        return Err(Error::Command(status));
+    }
+    transform(&mut buf[..len], flags);
+    Ok(len)
+}
+
+/// Transforms `pass` per `flags`, as `readpassphrase(3)` does with its input.
+fn transform(pass: &mut [u8], flags: Flags) {
+    for b in pass {
+        if flags.contains(Flags::SEVENBIT) {
+            *b &= 0x7f;
+        }
+        if flags.contains(Flags::FORCELOWER) {
+            b.make_ascii_lowercase();
+        }
+        if flags.contains(Flags::FORCEUPPER) {
+            b.make_ascii_uppercase();
+        }
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use std::{os::fd::AsRawFd, process};
+
+    use super::*;
+
+    #[test]
+    fn test_sources() {
+        let dir = env::temp_dir().join(format!("readpassphrase-source-{}", process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("file"), "file\r\nrest").unwrap();
+        let sources = Sources(vec![
+            Source::file(dir.join("missing")),
+            Source::file(dir.join("file")),
+        ]);
+        let (pass, source) = sources.getpass(c"").unwrap();
+        assert_eq!("file", pass);
+        assert_eq!(&sources.0[1], source);
+
+        let file = fs::File::open(dir.join("file")).unwrap();
+        let sources = Sources(vec![Source::fd(file.as_raw_fd())]);
+        let mut buf = [0u8; 4];
+        assert_eq!("fil", sources.read(c"", &mut buf).unwrap().0);
+        assert!(file.metadata().is_ok());
+        drop(file);
+
+        fs::remove_dir_all(&dir).unwrap();
+        let err = sources.getpass(c"").unwrap_err();
+        assert!(matches!(err, Error::Io(e) if e.kind() == io::ErrorKind::NotFound));
+    }
+
+    #[test]
+    fn test_command() {
+        let script = "printf 'Pass\\351word\\nmetadata\\n'; exit $0";
+        let sources = Sources(vec![Source::command(
+            ["sh", "-c", script, "0"],
+            Flags::SEVENBIT,
+        )]);
+        let (pass, source) = sources.getpass(c"").unwrap();
+        assert_eq!("Passiword", pass);
+        assert_eq!("command sh", source.to_string());
+
+        let sources = Sources(vec![Source::command(
+            ["sh", "-c", script, "3"],
+            Flags::empty(),
+        )]);
+        let err = sources.getpass(c"").unwrap_err();
+        assert!(matches!(err, Error::Command(status) if status.code() == Some(3)));
+
+        let mut pass = *b"Pass\xe9word";
+        transform(&mut pass, Flags::FORCEUPPER);
+        assert_eq!(b"PASS\xe9WORD", &pass);
+        transform(&mut pass, Flags::FORCELOWER | Flags::SEVENBIT);
+        assert_eq!(b"passiword", &pass);
+    }
+}
@@ -0,0 +1,377 @@
+//! Reading passphrases from systemd password agents.
+
+use std::{
+    ffi::CStr,
+    fmt::Write as _,
+    fs, io,
+    mem::{self, MaybeUninit},
+    os::{fd::AsRawFd, unix::net::UnixDatagram},
+    path::{Path, PathBuf},
+    process, ptr,
+    sync::atomic::{AtomicU32, Ordering},
+    time::{Duration, Instant, SystemTime},
+};
+
+use crate::{Backend, Error, Zeroize, backend};
+
+/// The directory that systemd password agents watch.
+const DEFAULT_DIR: &str = "/run/systemd/ask-password";
+
+/// A [`Backend`] that asks systemd password agents for the passphrase, using the
+/// [password agent protocol][0].
+///
+/// This is the way for services to ask for a passphrase, e.g. at boot: the request is shown by
+/// agents such as `systemd-tty-ask-password-agent` or a desktop’s agent, and is answered by
+/// whichever of them the user responds to.
+/// ```no_run
+/// # use std::time::Duration;
+/// # use readpassphrase_3::{Backend, Error, SystemdAskPassword};
+/// # fn main() -> Result<(), Error> {
+/// let ask = SystemdAskPassword {
+///     id: Some("myservice:database".into()),
+///     timeout: Some(Duration::from_secs(90)),
+///     ..Default::default()
+/// };
+/// let pass = ask.getpass(c"Database passphrase:")?;
+/// # Ok(())
+/// # }
+/// ```
+///
+/// Only replies from root or from the current user are accepted. If the agent declines to answer,
+/// [`Error::Cancelled`] is returned, and if the timeout passes first, [`Error::Io`] with
+/// [`io::ErrorKind::TimedOut`].
+///
+/// [0]: https://systemd.io/PASSWORD_AGENTS/
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct SystemdAskPassword {
+    /// The directory to publish the request in, `/run/systemd/ask-password` by default.
+    pub dir: PathBuf,
+    /// An identifier for what the passphrase is for, e.g. `cryptsetup:/dev/sda1`.
+    pub id: Option<String>,
+    /// How long to wait for a reply. If this is [`None`], wait indefinitely.
+    pub timeout: Option<Duration>,
+}
+
+impl Default for SystemdAskPassword {
+    fn default() -> Self {
+        SystemdAskPassword {
+            dir: DEFAULT_DIR.into(),
+            id: None,
+            timeout: None,
+        }
+    }
+}
+
+impl SystemdAskPassword {
+    fn run(&self, prompt: &CStr, buf: &mut [u8]) -> Result<usize, Error> {
+        let cap = buf
+            .len()
+            .checked_sub(1)
+            .ok_or(io::Error::from(io::ErrorKind::InvalidInput))?;
+        let deadline = self.timeout.map(|timeout| Instant::now() + timeout);
+        let files = Files::new(&self.dir);
+        let socket = UnixDatagram::bind(&files.socket)?;
+        set_passcred(&socket)?;
+        files.write_ask(&self.ask(prompt, &files.socket)?)?;
+        loop {
+            wait(&socket, deadline)?;
+            match recv(&socket, &mut buf[..cap])? {
+                Some(Reply::Password(len)) => return Ok(len),
+                Some(Reply::Cancelled) => return Err(Error::Cancelled),
+                // Ignore replies from other users, which may not be trusted.
+                None => buf.zeroize(),
+            }
+        }
+    }
+
+    /// Returns the contents of the ask file.
+    fn ask(&self, prompt: &CStr, socket: &Path) -> io::Result<String> {
+        let not_after = match self.timeout {
+            Some(timeout) => monotonic_now()?.saturating_add(timeout).as_micros(),
+            None => 0,
+        };
+        let mut ask = String::from("[Ask]\n");
+        _ = writeln!(ask, "PID={}", process::id());
+        _ = writeln!(ask, "Socket={}", socket.display());
+        _ = writeln!(ask, "AcceptCached=0\nEcho=0\nNotAfter={not_after}");
+        _ = writeln!(ask, "Message={}", escape(&prompt.to_string_lossy()));
+        if let Some(id) = &self.id {
+            _ = writeln!(ask, "Id={}", escape(id));
+        }
+        Ok(ask)
+    }
+}
+
+impl Backend for SystemdAskPassword {
+    fn read_bytes<'a>(
+        &self,
+        prompt: &CStr,
+        buf: &'a mut [MaybeUninit<u8>],
+    ) -> Result<&'a mut [u8], Error> {
+        backend::read_zeroed(buf, |buf| self.run(prompt, buf))
+    }
+}
+
+/// The ask file and reply socket of a request, which are removed when dropped.
+struct Files {
+    ask: PathBuf,
+    socket: PathBuf,
+}
+
+impl Files {
+    fn new(dir: &Path) -> Self {
+        static COUNT: AtomicU32 = AtomicU32::new(0);
+        let nanos = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap_or_default()
+            .subsec_nanos();
+        let name = format!(
+            "{}.{nanos:08x}.{}",
+            process::id(),
+            COUNT.fetch_add(1, Ordering::Relaxed)
+        );
+        Files {
+            ask: dir.join(format!("ask.{name}")),
+            socket: dir.join(format!("sck.{name}")),
+        }
+    }
+
+    /// Writes the ask file atomically, so that agents never see it partly written.
+    fn write_ask(&self, contents: &str) -> io::Result<()> {
+        let tmp = self.ask.with_file_name(format!(
+            ".tmp.{}",
+            self.ask.file_name().unwrap().to_string_lossy()
+        ));
+        fs::write(&tmp, contents)?;
+        fs::rename(&tmp, &self.ask).inspect_err(|_| _ = fs::remove_file(&tmp))
+    }
+}
+
+impl Drop for Files {
+    fn drop(&mut self) {
+        _ = fs::remove_file(&self.ask);
+        _ = fs::remove_file(&self.socket);
+    }
+}
+
+enum Reply {
+    Password(usize),
+    Cancelled,
+}
+
+/// Waits for `socket` to be readable, failing if `deadline` passes first.
+fn wait(socket: &UnixDatagram, deadline: Option<Instant>) -> io::Result<()> {
+    loop {
+        let timeout = match deadline {
+            Some(deadline) => {
+                let left = deadline.saturating_duration_since(Instant::now());
+                if left.is_zero() {
+                    return Err(io::ErrorKind::TimedOut.into());
+                }
+                // Round up, so as not to spin when less than a millisecond is left.
+                left.as_micros()
+                    .div_ceil(1000)
+                    .try_into()
+                    .unwrap_or(i32::MAX)
+            }
+            None => -1,
+        };
+        let mut fd = libc::pollfd {
+            fd: socket.as_raw_fd(),
+            events: libc::POLLIN,
+            revents: 0,
+        };
+        // SAFETY: `fd` is a valid `pollfd`.
+        match unsafe { libc::poll(&mut fd, 1, timeout) } {
+            -1 => {
+                let err = io::Error::last_os_error();
+                if err.kind() != io::ErrorKind::Interrupted {
+                    return Err(err);
+                }
+            }
+            0 => {}
+            _ => return Ok(()),
+        }
+    }
+}
+
+/// Receives a reply into `buf`, discarding anything that does not fit. Returns [`None`] if the
+/// reply was not sent by root or the current user.
+fn recv(socket: &UnixDatagram, buf: &mut [u8]) -> io::Result<Option<Reply>> {
+    let mut kind = 0u8;
+    let mut iov = [
+        libc::iovec {
+            iov_base: ptr::from_mut(&mut kind).cast(),
+            iov_len: 1,
+        },
+        libc::iovec {
+            iov_base: buf.as_mut_ptr().cast(),
+            iov_len: buf.len(),
+        },
+    ];
+    // A `u64` array, so that the control messages are suitably aligned.
+    let mut cmsg = [0u64; 8];
+    // SAFETY: `msghdr` is plain old data, for which zero is a valid value.
+    let mut msg: libc::msghdr = unsafe { mem::zeroed() };
+    msg.msg_iov = iov.as_mut_ptr();
+    msg.msg_iovlen = iov.len() as _;
+    msg.msg_control = cmsg.as_mut_ptr().cast();
+    msg.msg_controllen = mem::size_of_val(&cmsg) as _;
+    // SAFETY: `msg` points to valid buffers of the given sizes.
+    let n = unsafe { libc::recvmsg(socket.as_raw_fd(), &mut msg, libc::MSG_CMSG_CLOEXEC) };
+    let Ok(n) = usize::try_from(n) else {
+        return Err(io::Error::last_os_error());
+    };
+    // SAFETY: `msg` was filled in by `recvmsg`.
+    let uid = unsafe { sender_uid(&msg) };
+    // SAFETY: this has no preconditions.
+    let our_uid = unsafe { libc::getuid() };
+    if !uid.is_some_and(|uid| uid == 0 || uid == our_uid) {
+        return Ok(None);
+    }
+    match kind {
+        b'+' => Ok(Some(Reply::Password(n.saturating_sub(1).min(buf.len())))),
+        b'-' => Ok(Some(Reply::Cancelled)),
+        _ => Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "invalid password agent reply",
+        )),
+    }
+}
+
+/// Returns the sender’s uid from the `SCM_CREDENTIALS` message in `msg`, if there is one.
+///
+/// # Safety
+/// `msg` must have been filled in by `recvmsg(2)`.
+unsafe fn sender_uid(msg: &libc::msghdr) -> Option<libc::uid_t> {
+    // SAFETY: per this function’s safety requirements, the control messages are valid.
+    unsafe {
+        let mut cmsg = libc::CMSG_FIRSTHDR(msg);
+        while let Some(hdr) = cmsg.as_ref() {
+            if hdr.cmsg_level == libc::SOL_SOCKET && hdr.cmsg_type == libc::SCM_CREDENTIALS {
+                let cred = libc::CMSG_DATA(cmsg).cast::<libc::ucred>().read_unaligned();
+                return Some(cred.uid);
+            }
+            cmsg = libc::CMSG_NXTHDR(msg, cmsg);
+        }
+    }
+    None
+}
+
+/// Has the kernel attach the sender’s credentials to each datagram received on `socket`.
+fn set_passcred(socket: &UnixDatagram) -> io::Result<()> {
+    let on: libc::c_int = 1;
+    // SAFETY: `on` is a valid `c_int` option value.
+    let res = unsafe {
+        libc::setsockopt(
+            socket.as_raw_fd(),
+            libc::SOL_SOCKET,
+            libc::SO_PASSCRED,
+            ptr::from_ref(&on).cast(),
+            mem::size_of_val(&on) as libc::socklen_t,
+        )
+    };
+    if res == -1 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// Returns the time on `CLOCK_MONOTONIC`, which `NotAfter` is given in.
+fn monotonic_now() -> io::Result<Duration> {
+    let mut ts = MaybeUninit::uninit();
+    // SAFETY: `ts` is valid for writes.
+    if unsafe { libc::clock_gettime(libc::CLOCK_MONOTONIC, ts.as_mut_ptr()) } == -1 {
+        return Err(io::Error::last_os_error());
+    }
+    // SAFETY: `clock_gettime` succeeded, so `ts` is initialized.
+    let ts = unsafe { ts.assume_init() };
+    Ok(Duration::new(ts.tv_sec as u64, ts.tv_nsec as u32))
+}
+
+/// Escapes `s` as a C string, as systemd does for ask file values.
+fn escape(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            '\t' => escaped.push_str("\\t"),
+            c if c.is_ascii_control() => _ = write!(escaped, "\\x{:02x}", c as u8),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, thread};
+
+    use super::*;
+
+    /// A stand-in password agent, which answers the first request in `dir` with `reply`, or with
+    /// the request’s message if `reply` is [`None`].
+    fn agent(dir: PathBuf, reply: Option<&'static [u8]>) -> thread::JoinHandle<()> {
+        thread::spawn(move || {
+            let ask = loop {
+                let ask = fs::read_dir(&dir).unwrap().find_map(|entry| {
+                    let path = entry.unwrap().path();
+                    let name = path.file_name().unwrap().to_str().unwrap();
+                    name.starts_with("ask.").then_some(path)
+                });
+                match ask {
+                    Some(ask) => break fs::read_to_string(ask).unwrap(),
+                    None => thread::sleep(Duration::from_millis(10)),
+                }
+            };
+            let value = |key: &str| {
+                ask.lines()
+                    .find_map(|line| line.strip_prefix(key))
+                    .unwrap()
+                    .to_string()
+            };
+            let reply = match reply {
+                Some(reply) => reply.to_vec(),
+                None => format!("+{}", value("Message=")).into_bytes(),
+            };
+            let socket = UnixDatagram::unbound().unwrap();
+            socket.send_to(&reply, value("Socket=")).unwrap();
+        })
+    }
+
+    #[test]
+    fn test_systemd_ask_password() {
+        let dir = env::temp_dir().join(format!("readpassphrase-ask-{}", process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let ask = SystemdAskPassword {
+            dir: dir.clone(),
+            id: Some("test:ask".into()),
+            timeout: Some(Duration::from_secs(10)),
+        };
+
+        let handle = agent(dir.clone(), None);
+        let pass = ask.getpass(c"pass\\word\n").unwrap();
+        assert_eq!("pass\\\\word\\n", pass);
+        handle.join().unwrap();
+
+        let handle = agent(dir.clone(), Some(b"+password"));
+        let mut buf = [0u8; 5];
+        assert_eq!("pass", ask.readpassphrase(c"", &mut buf).unwrap());
+        handle.join().unwrap();
+
+        let handle = agent(dir.clone(), Some(b"-"));
+        let err = ask.getpass(c"").unwrap_err();
+        assert!(matches!(err, Error::Cancelled));
+        handle.join().unwrap();
+
+        let ask = SystemdAskPassword {
+            timeout: Some(Duration::from_millis(10)),
+            ..ask
+        };
+        let err = ask.getpass(c"").unwrap_err();
+        assert!(matches!(err, Error::Io(e) if e.kind() == io::ErrorKind::TimedOut));
+        assert_eq!(0, fs::read_dir(&dir).unwrap().count());
+        fs::remove_dir(&dir).unwrap();
+    }
+}
@@ -0,0 +1,172 @@
+//! Terminal capability detection and control.
+
+use std::{
+    cmp, env,
+    ffi::{CStr, OsStr},
+};
+
+use crate::Flags;
+
+/// What escape sequences may be written along with a prompt.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Capability {
+    /// The prompt goes somewhere other than a terminal, or to a dumb terminal.
+    None,
+    /// The prompt goes to a terminal, but `NO_COLOR` is set.
+    NoColor,
+    /// The prompt goes to a terminal that supports styling.
+    Full,
+}
+
+impl Capability {
+    /// Returns what the prompt target of `readpassphrase(3)` with `flags` supports.
+    ///
+    /// `readpassphrase(3)` writes its prompt to `/dev/tty` unless [`Flags::STDIN`] is passed or
+    /// `/dev/tty` cannot be opened, in which case it falls back to stderr. We never style the
+    /// fallback path.
+    pub(crate) fn of_prompt(flags: Flags) -> Capability {
+        if cfg!(windows) || flags.contains(Flags::STDIN) || !tty_available() {
+            return Capability::None;
+        }
+        Capability::from_env(
+            env::var_os("TERM").as_deref(),
+            env::var_os("NO_COLOR").as_deref(),
+        )
+    }
+
+    fn from_env(term: Option<&OsStr>, no_color: Option<&OsStr>) -> Capability {
+        match term {
+            None => Capability::None,
+            Some(term) if term.is_empty() || term == "dumb" => Capability::None,
+            // See <https://no-color.org>.
+            Some(_) if no_color.is_some_and(|v| !v.is_empty()) => Capability::NoColor,
+            Some(_) => Capability::Full,
+        }
+    }
+}
+
+/// Erases `prompt` and the `input` read after it from the terminal.
+///
+/// This is called after `readpassphrase(3)` returns, at which point the cursor is at the start of
+/// the line after the input. Nothing is done unless the prompt was written to a terminal whose
+/// width we can determine.
+pub(crate) fn erase_prompt(prompt: &CStr, input: &[u8], flags: Flags) {
+    if Capability::of_prompt(flags) == Capability::None {
+        return;
+    }
+    let echoed = if flags.contains(Flags::ECHO_ON) {
+        display_width(input)
+    } else {
+        0
+    };
+    imp::erase(prompt.to_bytes(), echoed);
+}
+
+/// Returns the sequence to erase a prompt of `prompt` followed by `echoed` columns of input on a
+/// terminal `cols` columns wide.
+fn erase_sequence(prompt: &[u8], echoed: usize, cols: usize) -> String {
+    let mut lines = prompt
+        .split(|&b| b == b'\n')
+        .map(display_width)
+        .collect::<Vec<_>>();
+    *lines.last_mut().unwrap() += echoed;
+    let rows: usize = lines.iter().map(|&w| cmp::max(1, w.div_ceil(cols))).sum();
+    format!("\r\x1b[{rows}A\x1b[J")
+}
+
+/// Returns the approximate number of columns `text` takes up, ignoring CSI escape sequences.
+fn display_width(text: &[u8]) -> usize {
+    let mut width = 0;
+    let text = String::from_utf8_lossy(text);
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\x1b' => {
+                if chars.next() == Some('[') {
+                    for c in chars.by_ref() {
+                        if ('\x40'..='\x7e').contains(&c) {
+                            break;
+                        }
+                    }
+                }
+            }
+            // Echoed control characters are shown in caret notation, e.g. `^C`.
+            '\0'..='\x1f' | '\x7f' => width += 2,
+            _ => width += 1,
+        }
+    }
+    width
+}
+
+#[cfg(not(windows))]
+mod imp {
+    use std::{fs::OpenOptions, io::Write, os::fd::AsRawFd};
+
+    pub(super) fn erase(prompt: &[u8], echoed: usize) {
+        let Ok(mut tty) = OpenOptions::new().write(true).open("/dev/tty") else {
+            return;
+        };
+        let mut ws = libc::winsize {
+            ws_row: 0,
+            ws_col: 0,
+            ws_xpixel: 0,
+            ws_ypixel: 0,
+        };
+        // SAFETY: `TIOCGWINSZ` writes a `winsize` to its pointer argument.
+        let res = unsafe { libc::ioctl(tty.as_raw_fd(), libc::TIOCGWINSZ, &mut ws) };
+        if res != 0 || ws.ws_col == 0 {
+            return;
+        }
+        let seq = super::erase_sequence(prompt, echoed, ws.ws_col.into());
+        _ = tty.write_all(seq.as_bytes());
+    }
+}
+
+#[cfg(windows)]
+mod imp {
+    pub(super) fn erase(_prompt: &[u8], _echoed: usize) {}
+}
+
+#[cfg(not(windows))]
+pub(crate) fn tty_available() -> bool {
+    use std::{fs::OpenOptions, io::IsTerminal};
+
+    OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open("/dev/tty")
+        .is_ok_and(|tty| tty.is_terminal())
+}
+
+#[cfg(windows)]
+pub(crate) fn tty_available() -> bool {
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_capability_from_env() {
+        let from_env = |term: Option<&str>, no_color: Option<&str>| {
+            Capability::from_env(term.map(OsStr::new), no_color.map(OsStr::new))
+        };
+        assert_eq!(Capability::None, from_env(None, None));
+        assert_eq!(Capability::None, from_env(Some("dumb"), None));
+        assert_eq!(Capability::None, from_env(Some("dumb"), Some("1")));
+        assert_eq!(Capability::NoColor, from_env(Some("xterm"), Some("1")));
+        assert_eq!(Capability::Full, from_env(Some("xterm"), Some("")));
+        assert_eq!(Capability::Full, from_env(Some("xterm-256color"), None));
+    }
+
+    #[test]
+    fn test_erase_sequence() {
+        assert_eq!("\r\x1b[1A\x1b[J", erase_sequence(b"Pass: ", 0, 80));
+        assert_eq!("\r\x1b[2A\x1b[J", erase_sequence(b"Pass: ", 80, 80));
+        assert_eq!("\r\x1b[2A\x1b[J", erase_sequence(b"hint\nPass: ", 74, 80));
+        assert_eq!("\r\x1b[3A\x1b[J", erase_sequence(b"hint\nPass: ", 75, 80));
+        let styled = b"\x1b[2mhint\x1b[0m\n\x1b[1m\x1b[31mPass: \x1b[0m";
+        assert_eq!("\r\x1b[3A\x1b[J", erase_sequence(styled, 75, 80));
+    }
+}
@@ -0,0 +1,8 @@
+#!/bin/sh
+# A fake askpass for testing. A prompt of `exit N` exits with status N, and `secret` prints a
+# fixed passphrase; any other prompt is printed back as the passphrase.
+case $1 in
+"exit "*) exit "${1#exit }" ;;
+secret) echo correct-horse-battery-staple ;;
+*) echo "$1" ;;
+esac
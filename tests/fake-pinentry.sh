@@ -0,0 +1,19 @@
+#!/bin/sh
+# A fake pinentry for testing, which answers `GETPIN` with the prompt it was given, unless the
+# description is `cancel`, `fail` or `secret`.
+echo "OK Pleased to meet you"
+while read -r cmd arg; do
+    case $cmd in
+    SETPROMPT) prompt=$arg; echo OK ;;
+    SETDESC) desc=$arg; echo OK ;;
+    GETPIN)
+        case $desc in
+        cancel) echo "ERR 83886179 Operation cancelled <Pinentry>" ;;
+        fail) echo "ERR 83886254 Inappropriate ioctl for device <Pinentry>" ;;
+        secret) echo "D correct-horse-battery-staple"; echo OK ;;
+        *) echo "# a comment"; echo "S PASSWORD_FROMCACHE"; echo "D $prompt"; echo OK ;;
+        esac ;;
+    BYE) echo "OK closing connection"; exit 0 ;;
+    *) echo OK ;;
+    esac
+done
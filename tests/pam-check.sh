@@ -0,0 +1,3 @@
+#!/bin/sh
+# Run by `pam_exec` in tests/pam.rs, which passes the password on stdin followed by a NUL.
+tr -d '\0' | grep -qx secret
@@ -0,0 +1,99 @@
+//! Authenticates against local PAM services using `PamConversation`.
+//!
+//! The services are written to a temporary directory and loaded with `pam_start_confdir(3)`, so
+//! that only stock modules are needed. Responses are fed through a pipe on stdin.
+#![cfg(all(target_os = "linux", feature = "pam"))]
+
+use std::{
+    env,
+    ffi::{CStr, c_char, c_int, c_void},
+    fs,
+    os::unix::ffi::OsStrExt,
+    process, ptr,
+};
+
+use readpassphrase_3::{Flags, PamConv, PamConversation};
+
+const PAM_SUCCESS: c_int = 0;
+
+#[link(name = "pam")]
+unsafe extern "C" {
+    fn pam_start_confdir(
+        service_name: *const c_char,
+        user: *const c_char,
+        pam_conversation: *const PamConv,
+        confdir: *const c_char,
+        pamh: *mut *mut c_void,
+    ) -> c_int;
+    fn pam_authenticate(pamh: *mut c_void, flags: c_int) -> c_int;
+    fn pam_end(pamh: *mut c_void, pam_status: c_int) -> c_int;
+}
+
+/// Puts `input` on stdin.
+fn set_stdin(input: &[u8]) {
+    let mut fds = [0; 2];
+    // SAFETY: `pipe` writes two fds to its argument.
+    assert_eq!(0, unsafe { libc::pipe(fds.as_mut_ptr()) });
+    // SAFETY: `input` is readable for its length, and `fds` are ours.
+    unsafe {
+        assert_eq!(
+            input.len() as isize,
+            libc::write(fds[1], input.as_ptr().cast(), input.len())
+        );
+        libc::close(fds[1]);
+        assert_eq!(0, libc::dup2(fds[0], 0));
+        libc::close(fds[0]);
+    }
+}
+
+/// Authenticates with `service` in `confdir`, returning the PAM status.
+fn authenticate(service: &CStr, confdir: &CStr, input: &[u8]) -> c_int {
+    set_stdin(input);
+    let conversation = PamConversation::new(Flags::STDIN.into());
+    let conv = conversation.pam_conv();
+    let mut pamh = ptr::null_mut();
+    // SAFETY: the arguments are valid, and `conversation` outlives `pamh`.
+    unsafe {
+        let res = pam_start_confdir(
+            service.as_ptr(),
+            ptr::null(),
+            &conv,
+            confdir.as_ptr(),
+            &mut pamh,
+        );
+        assert_eq!(PAM_SUCCESS, res);
+        let res = pam_authenticate(pamh, 0);
+        pam_end(pamh, res);
+        res
+    }
+}
+
+#[test]
+fn test_pam_conversation() {
+    let dir = env::temp_dir().join(format!("readpassphrase-pam-{}", process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let check = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/pam-check.sh");
+    fs::write(
+        dir.join("test"),
+        format!(
+            "auth required pam_permit.so\n\
+             auth required pam_echo.so Hello %u\n\
+             auth required pam_exec.so expose_authtok quiet {check}\n"
+        ),
+    )
+    .unwrap();
+    fs::write(dir.join("deny"), "auth required pam_deny.so\n").unwrap();
+    let confdir = [dir.as_os_str().as_bytes(), b"\0"].concat();
+    let confdir = CStr::from_bytes_with_nul(&confdir).unwrap();
+
+    assert_eq!(
+        PAM_SUCCESS,
+        authenticate(c"test", confdir, b"user\nsecret\n")
+    );
+    assert_ne!(
+        PAM_SUCCESS,
+        authenticate(c"test", confdir, b"user\nwrong\n")
+    );
+    assert_ne!(PAM_SUCCESS, authenticate(c"deny", confdir, b""));
+    fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,51 @@
+//! Reads passphrases from sources that depend on the environment.
+//!
+//! This is the only test in its binary, so that no other thread accesses the environment while it
+//! is modified.
+
+use std::{env, fs, process};
+
+use readpassphrase_3::{Source, Sources};
+
+#[test]
+fn test_env_sources() {
+    let dir = env::temp_dir().join(format!("readpassphrase-sources-{}", process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    fs::write(dir.join("credential"), "credential").unwrap();
+    let sources = Sources(vec![
+        Source::env("READPASSPHRASE_TEST_ENV"),
+        // SAFETY: no other thread accesses the environment while `sources` is read.
+        unsafe { Source::env_remove("READPASSPHRASE_TEST_ENV_REMOVE") },
+        Source::credential("credential"),
+    ]);
+
+    // SAFETY: no other thread accesses the environment during this test.
+    unsafe { env::set_var("READPASSPHRASE_TEST_ENV", "env\n") };
+    let (pass, source) = sources.getpass(c"").unwrap();
+    assert_eq!("env", pass);
+    assert_eq!(&sources.0[0], source);
+    assert!(env::var_os("READPASSPHRASE_TEST_ENV").is_some());
+    // SAFETY: as above.
+    unsafe { env::remove_var("READPASSPHRASE_TEST_ENV") };
+
+    // SAFETY: as above.
+    unsafe { env::set_var("READPASSPHRASE_TEST_ENV_REMOVE", "removed") };
+    let (pass, source) = sources.getpass(c"").unwrap();
+    assert_eq!("removed", pass);
+    assert_eq!(
+        "environment variable READPASSPHRASE_TEST_ENV_REMOVE",
+        source.to_string()
+    );
+    assert!(env::var_os("READPASSPHRASE_TEST_ENV_REMOVE").is_none());
+
+    // SAFETY: as above.
+    unsafe { env::set_var("CREDENTIALS_DIRECTORY", &dir) };
+    let (pass, source) = sources.getpass(c"").unwrap();
+    assert_eq!("credential", pass);
+    assert_eq!("credential credential", source.to_string());
+    // SAFETY: as above.
+    unsafe { env::remove_var("CREDENTIALS_DIRECTORY") };
+
+    fs::remove_dir_all(&dir).unwrap();
+    assert!(sources.getpass(c"").is_err());
+}
@@ -0,0 +1,584 @@
+//! Checks that secrets do not survive in freed heap memory.
+//!
+//! Every deallocation is scanned for [`SECRET`]; finding it means a passphrase was freed without
+//! being zeroed. Passphrases are fed to `readpassphrase(3)` through a pipe on stdin, or come from
+//! a stub askpass program.
+#![cfg(unix)]
+
+use std::{
+    alloc::{GlobalAlloc, Layout, System},
+    ffi::CStr,
+    sync::{
+        Mutex,
+        atomic::{AtomicUsize, Ordering},
+    },
+};
+
+use readpassphrase_3::{
+    AskPass, Backend, Control, Error, Flags, GitCredential, MAX_CAPACITY, Options, Passphrase,
+    PassphraseArray, Pinentry, Source, Sources, Zeroize, Zeroizing, readpassphrase,
+    readpassphrase_array, readpassphrase_array_in_place, readpassphrase_into, with_passphrase,
+};
+
+const SECRET: &[u8] = b"correct-horse-battery-staple";
+const PROMPT: &CStr = c"";
+const FLAGS: Flags = Flags::STDIN;
+const FAKE_ASKPASS: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fake-askpass.sh");
+const FAKE_PINENTRY: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fake-pinentry.sh");
+
+static LEAKS: AtomicUsize = AtomicUsize::new(0);
+
+struct ScanningAlloc;
+
+// SAFETY: this delegates to `System`, zeroing new memory so that it may be scanned when freed.
+unsafe impl GlobalAlloc for ScanningAlloc {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        // SAFETY: as guaranteed by the caller.
+        unsafe { System.alloc_zeroed(layout) }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        // SAFETY: `ptr` is an allocation of `layout.size()` bytes, which were all initialized
+        // when it was allocated.
+        let mem = unsafe { std::slice::from_raw_parts(ptr, layout.size()) };
+        if mem.windows(SECRET.len()).any(|w| w == SECRET) {
+            LEAKS.fetch_add(1, Ordering::SeqCst);
+        }
+        // SAFETY: as guaranteed by the caller.
+        unsafe { System.dealloc(ptr, layout) }
+    }
+}
+
+#[global_allocator]
+static ALLOC: ScanningAlloc = ScanningAlloc;
+
+/// Serializes tests, since they share stdin and the leak count.
+static STDIN: Mutex<()> = Mutex::new(());
+
+/// Runs `f` with `prefix`, [`SECRET`] and a newline on stdin, returning the number of leaks during
+/// the call.
+fn leaks(prefix: &[u8], f: impl FnOnce()) -> usize {
+    with_stdin(Some(prefix), f)
+}
+
+/// Runs `f` with stdin unreadable, so that reading a passphrase fails, returning the number of
+/// leaks during the call.
+fn leaks_on_error(f: impl FnOnce()) -> usize {
+    with_stdin(None, f)
+}
+
+fn with_stdin(prefix: Option<&[u8]>, f: impl FnOnce()) -> usize {
+    let _lock = STDIN.lock().unwrap_or_else(|e| e.into_inner());
+    let mut fds = [0; 2];
+    // SAFETY: `pipe` writes two fds to its argument.
+    assert_eq!(0, unsafe { libc::pipe(fds.as_mut_ptr()) });
+    // SAFETY: `input` is readable for its length, and `fds` are ours.
+    unsafe {
+        match prefix {
+            Some(prefix) => {
+                // Build the input while holding the lock, so that freeing it is not counted
+                // against another test.
+                let input = [prefix, SECRET, b"\n"].concat();
+                assert_eq!(
+                    input.len() as isize,
+                    libc::write(fds[1], input.as_ptr().cast(), input.len())
+                );
+                drop(input);
+                assert_eq!(0, libc::dup2(fds[0], 0));
+            }
+            // Reading the write end of a pipe fails with `EBADF`.
+            None => assert_eq!(0, libc::dup2(fds[1], 0)),
+        }
+        libc::close(fds[0]);
+        libc::close(fds[1]);
+    }
+    LEAKS.store(0, Ordering::SeqCst);
+    f();
+    LEAKS.load(Ordering::SeqCst)
+}
+
+/// Returns a buffer holding [`SECRET`] in spare capacity past what is read into, as if left over
+/// from earlier use, so that only wiping the whole buffer removes it.
+fn stale_buf() -> Vec<u8> {
+    let mut buf = Vec::with_capacity(MAX_CAPACITY + SECRET.len());
+    let spare = &mut buf.spare_capacity_mut()[MAX_CAPACITY..];
+    for (dst, &b) in spare.iter_mut().zip(SECRET) {
+        dst.write(b);
+    }
+    buf
+}
+
+#[test]
+fn test_readpassphrase() {
+    assert_eq!(
+        0,
+        leaks(b"", || {
+            let mut buf = Zeroizing::new(vec![0u8; 64]);
+            let pass = readpassphrase(PROMPT, &mut buf, FLAGS).unwrap();
+            assert_eq!(SECRET, pass.as_bytes());
+        })
+    );
+}
+
+#[test]
+fn test_readpassphrase_into() {
+    assert_eq!(
+        0,
+        leaks(b"", || {
+            let mut pass = readpassphrase_into(PROMPT, Vec::with_capacity(64), FLAGS).unwrap();
+            assert_eq!(SECRET, pass.as_bytes());
+            pass.zeroize();
+        })
+    );
+    assert_eq!(
+        0,
+        leaks(b"", || {
+            let buf = Zeroizing::new(Vec::with_capacity(64));
+            let pass = readpassphrase_into(PROMPT, buf, FLAGS).unwrap();
+            assert_eq!(SECRET, pass.as_bytes());
+        })
+    );
+    assert_eq!(
+        0,
+        leaks(b"", || {
+            let buf = vec![0u8; 64].into_boxed_slice();
+            let pass: Passphrase<_> = readpassphrase_into(PROMPT, buf, FLAGS).unwrap();
+            assert_eq!(SECRET, pass.as_bytes());
+        })
+    );
+}
+
+#[test]
+fn test_readpassphrase_into_utf8_error() {
+    assert_eq!(
+        0,
+        leaks(b"\xff", || {
+            let err = readpassphrase_into(PROMPT, vec![0u8; 64], FLAGS).unwrap_err();
+            assert!(matches!(err.error(), Error::Utf8(_)));
+        })
+    );
+    assert_eq!(
+        0,
+        leaks(b"\xff", || {
+            let err = readpassphrase_into(PROMPT, vec![0u8; 64], FLAGS).unwrap_err();
+            assert!(matches!(Error::from(err), Error::Utf8(_)));
+        })
+    );
+    assert_eq!(
+        0,
+        leaks(b"\xff", || {
+            let err = readpassphrase_into(PROMPT, vec![0u8; 64], FLAGS).unwrap_err();
+            err.into_bytes().zeroize();
+        })
+    );
+}
+
+#[test]
+fn test_readpassphrase_into_io_error() {
+    assert_eq!(
+        0,
+        leaks_on_error(|| {
+            let err = readpassphrase_into(PROMPT, stale_buf(), FLAGS).unwrap_err();
+            assert!(matches!(err.error(), Error::Io(_)));
+        })
+    );
+    assert_eq!(
+        0,
+        leaks_on_error(|| {
+            let err = readpassphrase_into(PROMPT, stale_buf(), FLAGS).unwrap_err();
+            let mut buf = err.into_bytes();
+            assert!(buf.is_empty());
+            buf.zeroize();
+        })
+    );
+    assert_eq!(
+        1,
+        leaks_on_error(|| {
+            let err = readpassphrase_into(PROMPT, stale_buf(), FLAGS).unwrap_err();
+            drop(err.into_bytes());
+        })
+    );
+}
+
+#[test]
+fn test_readpassphrase_into_control_error() {
+    let options = Options {
+        flags: FLAGS,
+        control: Control::Reject,
+        ..Default::default()
+    };
+    assert_eq!(
+        0,
+        leaks(b"\x1b", || {
+            let err = options
+                .readpassphrase_into(PROMPT, vec![0u8; 64])
+                .unwrap_err();
+            assert!(matches!(err.error(), Error::Control));
+        })
+    );
+}
+
+#[test]
+fn test_with_passphrase() {
+    assert_eq!(
+        0,
+        leaks(b"", || {
+            let len = with_passphrase(PROMPT, FLAGS, |pass| pass.len()).unwrap();
+            assert_eq!(SECRET.len(), len);
+        })
+    );
+}
+
+#[test]
+fn test_readpassphrase_array() {
+    // The array itself is on the stack; this checks that nothing is left on the heap.
+    assert_eq!(
+        0,
+        leaks(b"", || {
+            let pass = readpassphrase_array::<64>(PROMPT, FLAGS).unwrap();
+            assert_eq!(SECRET, pass.as_bytes());
+        })
+    );
+    let mut pass = PassphraseArray::<64>::new();
+    assert_eq!(
+        0,
+        leaks(b"", || {
+            readpassphrase_array_in_place(PROMPT, &mut pass, FLAGS).unwrap();
+            assert_eq!(SECRET, pass.as_bytes());
+        })
+    );
+    assert_eq!(
+        0,
+        leaks_on_error(|| {
+            assert!(readpassphrase_array_in_place(PROMPT, &mut pass, FLAGS).is_err());
+            assert!(pass.is_empty());
+        })
+    );
+}
+
+#[test]
+fn test_readpassphrase_locked() {
+    // The locked buffer is not on the heap; this checks that nothing is left there.
+    assert_eq!(
+        0,
+        leaks(b"", || {
+            let pass = Options::from(FLAGS).readpassphrase_locked(PROMPT).unwrap();
+            assert_eq!(SECRET, pass.as_bytes());
+        })
+    );
+}
+
+#[test]
+fn test_sources() {
+    let sources = Sources(vec![Source::fd(0)]);
+    assert_eq!(
+        0,
+        leaks(b"", || {
+            let (mut pass, _) = sources.getpass(PROMPT).unwrap();
+            assert_eq!(SECRET, pass.as_bytes());
+            pass.zeroize();
+        })
+    );
+    assert_eq!(
+        0,
+        leaks(b"", || {
+            let buf = Zeroizing::new(Vec::with_capacity(64));
+            let (pass, _) = sources.read_into(PROMPT, buf).unwrap();
+            assert_eq!(SECRET, pass.as_bytes());
+        })
+    );
+}
+
+#[test]
+fn test_backend() {
+    let askpass = AskPass::new(FAKE_ASKPASS);
+    assert_eq!(
+        0,
+        leaks(b"", || {
+            let mut pass = askpass.getpass(c"secret").unwrap();
+            assert_eq!(SECRET, pass.as_bytes());
+            pass.zeroize();
+        })
+    );
+    assert_eq!(
+        0,
+        leaks(b"", || {
+            let buf = Zeroizing::new(Vec::with_capacity(64));
+            let pass = askpass.readpassphrase_into(c"secret", buf).unwrap();
+            assert_eq!(SECRET, pass.as_bytes());
+        })
+    );
+    assert_eq!(
+        0,
+        leaks(b"", || {
+            let err = askpass
+                .readpassphrase_into(c"exit 2", stale_buf())
+                .unwrap_err();
+            assert!(matches!(err.error(), Error::Io(_)));
+        })
+    );
+}
+
+#[test]
+fn test_getpass() {
+    let options = Options::from(FLAGS);
+    assert_eq!(
+        0,
+        leaks(b"", || {
+            let mut pass = options.getpass(PROMPT).unwrap();
+            assert_eq!(SECRET, pass.as_bytes());
+            pass.zeroize();
+        })
+    );
+    assert_eq!(
+        0,
+        leaks(b"\xff", || {
+            let err = options.getpass(PROMPT).unwrap_err();
+            assert!(matches!(err, Error::Utf8(_)));
+        })
+    );
+}
+
+#[test]
+fn test_pinentry() {
+    let mut pinentry = Pinentry::new(FAKE_PINENTRY);
+    pinentry.description = Some("secret".into());
+    assert_eq!(
+        0,
+        leaks(b"", || {
+            let mut pass = pinentry.getpass(PROMPT).unwrap();
+            assert_eq!(SECRET, pass.as_bytes());
+            pass.zeroize();
+        })
+    );
+    pinentry.description = Some("fail".into());
+    assert_eq!(
+        0,
+        leaks(b"", || {
+            let err = pinentry
+                .readpassphrase_into(PROMPT, stale_buf())
+                .unwrap_err();
+            assert!(matches!(err.error(), Error::Io(_)));
+        })
+    );
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn test_systemd_ask_password() {
+    use std::{env, fs, os::unix::net::UnixDatagram, process, thread, time::Duration};
+
+    use readpassphrase_3::SystemdAskPassword;
+
+    let dir = env::temp_dir().join(format!("readpassphrase-zeroize-{}", process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let ask = SystemdAskPassword {
+        dir: dir.clone(),
+        id: None,
+        timeout: Some(Duration::from_secs(10)),
+    };
+    // A stand-in password agent, which replies to the first request from a buffer on its stack.
+    let agent = |dir: std::path::PathBuf| {
+        thread::spawn(move || {
+            let ask = loop {
+                let ask = fs::read_dir(&dir).unwrap().find_map(|entry| {
+                    let path = entry.unwrap().path();
+                    let name = path.file_name().unwrap().to_str().unwrap();
+                    name.starts_with("ask.").then_some(path)
+                });
+                match ask {
+                    Some(ask) => break fs::read_to_string(ask).unwrap(),
+                    None => thread::sleep(Duration::from_millis(10)),
+                }
+            };
+            let socket = ask
+                .lines()
+                .find_map(|line| line.strip_prefix("Socket="))
+                .unwrap();
+            let mut reply = [b'+'; 1 + SECRET.len()];
+            reply[1..].copy_from_slice(SECRET);
+            UnixDatagram::unbound()
+                .unwrap()
+                .send_to(&reply, socket)
+                .unwrap();
+            reply.zeroize();
+        })
+    };
+    assert_eq!(
+        0,
+        leaks(b"", || {
+            let handle = agent(dir.clone());
+            let mut pass = ask.getpass(PROMPT).unwrap();
+            assert_eq!(SECRET, pass.as_bytes());
+            pass.zeroize();
+            handle.join().unwrap();
+        })
+    );
+    fs::remove_dir(&dir).unwrap();
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn test_keyring_cache() {
+    use std::process;
+
+    use readpassphrase_3::KeyringCache;
+
+    // SAFETY: `KEYCTL_GET_KEYRING_ID` takes a keyring ID and whether to create it.
+    let res = unsafe {
+        libc::syscall(
+            libc::SYS_keyctl,
+            libc::KEYCTL_GET_KEYRING_ID,
+            libc::KEY_SPEC_SESSION_KEYRING,
+            0,
+        )
+    };
+    if res == -1 {
+        eprintln!("skipping: keyrings are unavailable");
+        return;
+    }
+    let description = format!("readpassphrase-zeroize:{}", process::id());
+    let cache = KeyringCache::new(description, AskPass::new(FAKE_ASKPASS));
+    cache.invalidate().unwrap();
+    assert_eq!(
+        0,
+        leaks(b"", || {
+            let mut pass = cache.getpass(c"secret").unwrap();
+            assert_eq!(SECRET, pass.as_bytes());
+            pass.zeroize();
+            // This is read from the keyring, as the askpass program would fail.
+            let mut pass = cache.getpass(c"exit 1").unwrap();
+            assert_eq!(SECRET, pass.as_bytes());
+            pass.zeroize();
+        })
+    );
+    cache.invalidate().unwrap();
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn test_agent_cache() {
+    use std::{env, process, thread, time::Duration};
+
+    use readpassphrase_3::{Agent, AgentCache};
+
+    let socket = env::temp_dir().join(format!("readpassphrase-zeroize-{}.sock", process::id()));
+    let mut agent = Agent::bind(&socket, Duration::from_secs(60)).unwrap();
+    thread::spawn(move || agent.run());
+    let cache = AgentCache {
+        socket: Some(socket),
+        key: "zeroize".into(),
+        backend: AskPass::new(FAKE_ASKPASS),
+    };
+    assert_eq!(
+        0,
+        leaks(b"", || {
+            let mut pass = cache.getpass(c"secret").unwrap();
+            assert_eq!(SECRET, pass.as_bytes());
+            pass.zeroize();
+            // This is read from the agent, as the askpass program would fail.
+            let mut pass = cache.getpass(c"exit 1").unwrap();
+            assert_eq!(SECRET, pass.as_bytes());
+            pass.zeroize();
+            cache.invalidate().unwrap();
+        })
+    );
+}
+
+#[test]
+fn test_git_credential() {
+    let input = b"protocol=https\nhost=example.com\npassword=correct-horse-battery-staple\n\n";
+    assert_eq!(
+        0,
+        leaks(b"", || {
+            let credential = GitCredential::read_from(&input[..]).unwrap();
+            assert_eq!(SECRET, credential.password().unwrap().as_bytes());
+            let mut out = Zeroizing::new(Vec::new());
+            credential.write_to(&mut *out).unwrap();
+            assert_eq!(&input[..], &out[..]);
+        })
+    );
+    let input = b"password=correct-horse-battery-staple\n\xff\n\n";
+    assert_eq!(
+        0,
+        leaks(b"", || {
+            let err = GitCredential::read_from(&input[..]).unwrap_err();
+            assert!(matches!(err, Error::Utf8(_)));
+        })
+    );
+}
+
+#[cfg(feature = "pam")]
+#[test]
+fn test_pam_conversation() {
+    use std::{ffi::c_int, ptr};
+
+    use readpassphrase_3::{PamConversation, PamMessage, PamResponse};
+
+    const PROMPT_ECHO_ON: c_int = 2;
+
+    let conversation = PamConversation::new(FLAGS.into());
+    let conv = conversation.pam_conv();
+    let msg = PamMessage {
+        msg_style: PROMPT_ECHO_ON,
+        msg: c"Username: ".as_ptr(),
+    };
+    // Responses are allocated with `malloc(3)` rather than on the Rust heap, so are zeroed and
+    // freed here.
+    let converse = || {
+        let mut msgs = [&raw const msg];
+        let mut resp: *mut PamResponse = ptr::null_mut();
+        // SAFETY: the arguments are as PAM would pass them.
+        let res = unsafe { conv.conv.unwrap()(1, msgs.as_mut_ptr(), &mut resp, conv.appdata_ptr) };
+        if res != 0 {
+            return None;
+        }
+        // SAFETY: on success, `resp` holds one response with a NUL-terminated string.
+        unsafe {
+            let text = (*resp).resp;
+            let len = libc::strlen(text);
+            let bytes = std::slice::from_raw_parts_mut(text.cast::<u8>(), len);
+            let matched = bytes == SECRET;
+            bytes.zeroize();
+            libc::free(text.cast());
+            libc::free(resp.cast());
+            Some(matched)
+        }
+    };
+    assert_eq!(0, leaks(b"", || assert_eq!(Some(true), converse())));
+    assert_eq!(0, leaks_on_error(|| assert_eq!(None, converse())));
+}
+
+#[cfg(feature = "secrecy")]
+#[test]
+fn test_readpassphrase_secret() {
+    use readpassphrase_3::readpassphrase_secret;
+    use secrecy::ExposeSecret;
+
+    assert_eq!(
+        0,
+        leaks(b"", || {
+            let pass = readpassphrase_secret(PROMPT, Vec::with_capacity(64), FLAGS).unwrap();
+            assert_eq!(SECRET, pass.expose_secret().as_bytes());
+        })
+    );
+    assert_eq!(
+        0,
+        leaks(b"", || {
+            let buf = secrecy::SecretBox::from(vec![0u8; 64]);
+            let pass: Passphrase<_> = readpassphrase_into(PROMPT, buf, FLAGS).unwrap();
+            assert_eq!(SECRET, pass.as_bytes());
+        })
+    );
+}
+
+#[test]
+fn test_leak_detected() {
+    assert_eq!(
+        1,
+        leaks(b"", || {
+            let pass = readpassphrase_into(PROMPT, vec![0u8; 64], FLAGS).unwrap();
+            drop(pass);
+        })
+    );
+}